@@ -0,0 +1,129 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::config::BindingConfig;
+use crate::input::*;
+use crate::keys::Button;
+
+// what the clock can do when a button is pressed
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Action {
+    Nothing,
+    Snooze,
+    Dismiss,
+    TogglePlayback,
+    SleepTimer,
+    VolumeUp,
+    VolumeDown,
+    NextStation,
+    PreviousStation,
+    ToggleAlarm,
+    ShowNextAlarm,
+    SkipNextAlarm,
+    ToggleCeiling,
+    DimUp,
+    DimDown,
+    RefreshRateUp,
+    RefreshRateDown,
+    NormalMode,
+    SpecialMode,
+    SettingsMode,
+    NapMode,
+    MenuPrevious,
+    MenuNext,
+    MenuSelect,
+    MenuBack,
+}
+
+// buttons do different things depending on the mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Mode {
+    Normal, Special, Settings, Nap
+}
+
+// default bindings, a long press does the same as a short press unless it has its own binding
+#[rustfmt::skip]
+const DEFAULT_BINDINGS: [(Mode, Button, Gesture, Action); 32] = [
+    (Mode::Normal,  Button::Snooze,   Gesture::Short, Action::Snooze),
+    (Mode::Normal,  Button::Snooze,   Gesture::Long,  Action::Dismiss),
+    (Mode::Normal,  Button::OnOff,    Gesture::Short, Action::TogglePlayback),
+    (Mode::Normal,  Button::OnOff,    Gesture::Long,  Action::SleepTimer),
+    (Mode::Normal,  Button::SpkrHigh, Gesture::Short, Action::VolumeUp),
+    (Mode::Normal,  Button::SpkrLow,  Gesture::Short, Action::VolumeDown),
+    (Mode::Normal,  Button::Time,     Gesture::Short, Action::ToggleAlarm),
+    (Mode::Normal,  Button::Time,     Gesture::Long,  Action::ShowNextAlarm),
+    (Mode::Normal,  Button::Left,     Gesture::Short, Action::PreviousStation),
+    (Mode::Normal,  Button::Right,    Gesture::Short, Action::NextStation),
+    (Mode::Normal,  Button::B1,       Gesture::Short, Action::SpecialMode),
+    (Mode::Normal,  Button::B1,       Gesture::Long,  Action::NapMode),
+    (Mode::Normal,  Button::B2,       Gesture::Short, Action::SettingsMode),
+    (Mode::Normal,  Button::B2,       Gesture::Long,  Action::SkipNextAlarm),
+    (Mode::Special, Button::Snooze,   Gesture::Short, Action::Snooze),
+    (Mode::Special, Button::Snooze,   Gesture::Long,  Action::Dismiss),
+    (Mode::Special, Button::Time,     Gesture::Short, Action::ToggleCeiling),
+    (Mode::Special, Button::Left,     Gesture::Short, Action::DimDown),
+    (Mode::Special, Button::Right,    Gesture::Short, Action::DimUp),
+    (Mode::Special, Button::SpkrLow,  Gesture::Short, Action::RefreshRateDown),
+    (Mode::Special, Button::SpkrHigh, Gesture::Short, Action::RefreshRateUp),
+    (Mode::Special, Button::B1,       Gesture::Short, Action::NormalMode),
+    (Mode::Settings, Button::Left,    Gesture::Short, Action::MenuPrevious),
+    (Mode::Settings, Button::Right,   Gesture::Short, Action::MenuNext),
+    (Mode::Settings, Button::Time,    Gesture::Short, Action::MenuSelect),
+    (Mode::Settings, Button::Snooze,  Gesture::Short, Action::MenuBack),
+    (Mode::Settings, Button::B2,      Gesture::Short, Action::NormalMode),
+    (Mode::Nap,     Button::Left,     Gesture::Short, Action::MenuPrevious),
+    (Mode::Nap,     Button::Right,    Gesture::Short, Action::MenuNext),
+    (Mode::Nap,     Button::Time,     Gesture::Short, Action::MenuSelect),
+    (Mode::Nap,     Button::Snooze,   Gesture::Short, Action::MenuBack),
+    (Mode::Nap,     Button::B1,       Gesture::Short, Action::NormalMode),
+];
+
+pub struct Bindings {
+    table: HashMap<(Mode, Button, Gesture), Action>,
+}
+
+impl Bindings {
+    // defaults, overridden by the configuration
+    pub fn new(config: &[BindingConfig]) -> Self {
+        let mut table: HashMap<(Mode, Button, Gesture), Action> = DEFAULT_BINDINGS.iter()
+            .map(|(mode, button, gesture, action)| ((*mode, *button, *gesture), *action))
+            .collect();
+        for binding in config {
+            table.insert((binding.mode, binding.button, binding.gesture), binding.action);
+        }
+        Bindings { table }
+    }
+
+    pub fn action(&self, mode: Mode, press: Press) -> Action {
+        let action = self.table.get(&(mode, press.button, press.gesture))
+            .or_else(|| self.table.get(&(mode, press.button, Gesture::Short)));
+        match action {
+            Some(a) => *a,
+            None => Action::Nothing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(button: Button, gesture: Gesture) -> Press {
+        Press { button, gesture }
+    }
+
+    #[test]
+    fn bindings() {
+        let config: Vec<BindingConfig> = vec![
+            BindingConfig { mode: Mode::Normal, button: Button::Left, gesture: Gesture::Short, action: Action::VolumeDown },
+            BindingConfig { mode: Mode::Special, button: Button::Left, gesture: Gesture::Long, action: Action::Nothing },
+        ];
+        let bindings = Bindings::new(&config);
+        assert_eq!(bindings.action(Mode::Normal, press(Button::Left, Gesture::Short)), Action::VolumeDown);
+        // a long press without its own binding is a short press
+        assert_eq!(bindings.action(Mode::Normal, press(Button::Left, Gesture::Long)), Action::VolumeDown);
+        assert_eq!(bindings.action(Mode::Special, press(Button::Left, Gesture::Long)), Action::Nothing);
+        assert_eq!(bindings.action(Mode::Special, press(Button::Left, Gesture::Short)), Action::DimDown);
+        assert_eq!(bindings.action(Mode::Settings, press(Button::OnOff, Gesture::Short)), Action::Nothing);
+    }
+}
@@ -0,0 +1,120 @@
+use chrono::*;
+use std::fs;
+use std::io;
+
+use crate::alarm::Alarm;
+use crate::caldav::CalDav;
+use crate::config::AgendaConfig;
+use crate::holidays::DaysOff;
+use crate::ical::*;
+use crate::rules::*;
+use crate::schedule::Recurrence;
+
+/* Wake up time from the agenda
+ *
+ * The alarm of a day is the start of its first event minus the preparation time,
+ * adjusted by the rules of the configuration.
+ *
+ * Events come from a local file, a CalDAV server or both.
+ */
+
+pub struct Agenda {
+    path: Option<String>,
+    caldav: Option<CalDav>,
+    preparation: Duration,
+    days: i64,
+    rules: Rules,
+}
+
+impl Agenda {
+    pub fn new(config: &AgendaConfig, days_off: DaysOff, state_dir: &str) -> Option<Self> {
+        if config.path.is_none() && config.caldav.is_none() { return None }
+        Some(Agenda {
+            path: config.path.clone(),
+            caldav: config.caldav.as_ref().map(|c| CalDav::new(c, state_dir)),
+            preparation: Duration::minutes(config.preparation_minutes),
+            days: config.days,
+            rules: Rules::new(&config.rules, days_off, Duration::minutes(config.preparation_minutes)),
+        })
+    }
+
+    // alarms for today and the following days
+    pub fn alarms(&self, now: DateTime<Local>) -> io::Result<Vec<Alarm>> {
+        let decisions = self.decisions(now, self.days)?;
+        Ok(decisions.iter().filter_map(|decision| {
+            let wake_up = decision.wake_up?;
+            Some(Alarm {
+                time: wake_up.time().with_second(0)?,
+                recurrence: Recurrence::Once(wake_up.date_naive()),
+                enabled: true,
+                // the rules already know about days off
+                skip_days_off: false,
+                station: None,
+                volume: None,
+            })
+        }).collect())
+    }
+
+    // one line per day with its alarm and the reason, for clock dry-run
+    pub fn dry_run(&self, now: DateTime<Local>, days: i64) -> io::Result<Vec<String>> {
+        Ok(self.decisions(now, days)?.iter().map(|decision| {
+            let time = decision.wake_up.map(|t| t.format("%H:%M").to_string()).unwrap_or_else(|| "--:--".to_string());
+            format!("{}  {}  {}", decision.day.format("%a %Y-%m-%d"), time, decision.reason)
+        }).collect())
+    }
+
+    // wake up time of each day, an error only when no source could be read
+    pub fn decisions(&self, now: DateTime<Local>, days: i64) -> io::Result<Vec<Decision>> {
+        // the widest commute is unknown here, a whole day before is enough
+        let from = now - self.preparation - Duration::days(1);
+        let to = now + Duration::days(days + 1);
+        let mut contents = Vec::new();
+        let mut error = None;
+        if let Some(ref path) = self.path {
+            match fs::read_to_string(path) {
+                Ok(c) => contents.push(c),
+                Err(e) => { println!("Cannot read agenda {}: {:?}", path, e); error = Some(e) },
+            }
+        }
+        if let Some(ref caldav) = self.caldav {
+            match caldav.calendar(from, to) {
+                Ok(c) => contents.push(c),
+                Err(e) => { println!("No calendar from server nor cache: {:?}", e); error = Some(e) },
+            }
+        }
+        if let (true, Some(e)) = (contents.is_empty(), error) { return Err(e) }
+        let events = events_between(&contents.join("\n"), from, to);
+        let today = now.date_naive();
+        Ok((0..days).map(|d| self.rules.decide(&events, today + Duration::days(d))).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::local;
+
+    #[test]
+    fn dry_run() {
+        let dir = std::env::temp_dir().join("clock-agenda-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agenda.ics");
+        fs::write(&path, "BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:a\nSUMMARY:standup\nDTSTART:20260311T083000\nEND:VEVENT\nEND:VCALENDAR\n").unwrap();
+        let config: AgendaConfig = toml::from_str(&format!(r#"
+            path = "{}"
+            preparation_minutes = 60
+            [rules.default]
+            time = "07:30"
+            weekdays = ["Mon", "Tue", "Wed", "Thu", "Fri"]
+        "#, path.display())).unwrap();
+        let agenda = Agenda::new(&config, DaysOff::default(), dir.to_str().unwrap()).unwrap();
+        let now = local(NaiveDate::from_ymd_opt(2026, 3, 9).unwrap().and_hms_opt(22, 0, 0).unwrap()).unwrap();
+        let lines = agenda.dry_run(now, 14).unwrap();
+        assert_eq!(lines.len(), 14);
+        assert_eq!(lines[0], "Mon 2026-03-09  07:30  no event, default alarm");
+        assert_eq!(lines[2], "Wed 2026-03-11  07:30  first event standup at 08:30, 60 min preparation");
+        assert_eq!(lines[5], "Sat 2026-03-14  --:--  no event");
+        assert_eq!(lines[13], "Sun 2026-03-22  --:--  no event");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
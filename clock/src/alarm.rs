@@ -0,0 +1,679 @@
+use chrono::{DateTime, Duration, Local, LocalResult, NaiveDateTime, NaiveTime, TimeZone};
+use serde::Serialize;
+
+use crate::config::{AlarmConfig, AlarmDefinition};
+use crate::holidays::DaysOff;
+use crate::overrides::Overrides;
+use crate::schedule::Recurrence;
+
+/* Alarm clock
+ *
+ *           alarm time              snooze delay over
+ *   Idle --------------> Ringing <------------------- Snoozed
+ *    ^                    |  |  \        snooze          ^
+ *    |   dismiss/timeout  |  |   +-----------------------+
+ *    +--------------------+  |
+ *    +------ dismiss --------+ (from Snoozed too)
+ *
+ * While ringing, the volume ramps up from a quiet start, and if nobody reacts
+ * the alarm escalates to the fallback file.
+ *
+ * Before the alarm, the dawn tells how much the ceiling light should be on.
+ *
+ * An alarm missed while the clock was off rings late, or is kept as an error when too late.
+ *
+ * The next occurrence can be skipped, and a nap rings once after some minutes,
+ * even when alarms are off.
+ *
+ * Like the ui, this doesn't touch the hardware, it returns events
+ * for the main thread to start and stop the sound.
+ */
+
+// station and volume of an alarm, player defaults when None
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sound {
+    pub station: Option<usize>,
+    pub volume: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmEvent {
+    Ring(Sound),
+    // ramp volume
+    Volume(u16),
+    // fallback file at this volume
+    Escalate(u16),
+    Silence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmState {
+    Idle,
+    Ringing { since: DateTime<Local>, sound: Sound },
+    Snoozed { until: DateTime<Local>, sound: Sound },
+}
+
+// what the left indicator shows
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Indicator {
+    // no alarm coming
+    None,
+    Armed,
+    // the next alarm won't ring
+    Skipped,
+    Ringing,
+    Snoozed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alarm {
+    pub time: NaiveTime,
+    pub recurrence: Recurrence,
+    pub enabled: bool,
+    pub skip_days_off: bool,
+    pub station: Option<usize>,
+    pub volume: Option<u16>,
+}
+
+// a whole year of days off is not a clock anymore
+const MAX_SKIPPED: usize = 366;
+// ticks come every second, a longer time means the system clock jumped forward
+const CLOCK_JUMP_SECONDS: i64 = 10;
+
+impl Alarm {
+    // first occurrence strictly after a moment
+    pub fn next_after(&self, after: DateTime<Local>, days_off: &DaysOff) -> Option<DateTime<Local>> {
+        if !self.enabled { return None }
+        let mut day = self.recurrence.next_date(after.date_naive())?;
+        // the first day may be over, a time can be moved by DST, and days off are skipped
+        for _ in 0..MAX_SKIPPED {
+            if !(self.skip_days_off && days_off.is_off(day)) {
+                if let Some(time) = local(day.and_time(self.time)) {
+                    if time > after { return Some(time) }
+                }
+            }
+            day = self.recurrence.next_date(day.succ_opt()?)?;
+        }
+        None
+    }
+
+    pub fn sound(&self) -> Sound {
+        Sound { station: self.station, volume: self.volume }
+    }
+}
+
+// invalid alarms are ignored, and said so
+pub fn load_alarms(definitions: &[AlarmDefinition]) -> Vec<Alarm> {
+    definitions.iter().filter_map(|definition| {
+        match NaiveTime::parse_from_str(&definition.time, "%H:%M") {
+            Ok(time) => Some(Alarm {
+                time,
+                recurrence: definition.repeat.clone(),
+                enabled: definition.enabled,
+                skip_days_off: definition.skip_days_off,
+                station: definition.station,
+                volume: definition.volume,
+            }),
+            Err(e) => { println!("Invalid alarm time {}: {}", definition.time, e); None },
+        }
+    }).collect()
+}
+
+// local time to absolute time, a time in the DST gap is moved one hour later
+pub fn local(time: NaiveDateTime) -> Option<DateTime<Local>> {
+    match Local.from_local_datetime(&time) {
+        LocalResult::Single(t) => Some(t),
+        // time happens twice, the alarm is only for the first one
+        LocalResult::Ambiguous(t, _) => Some(t),
+        LocalResult::None => Local.from_local_datetime(&(time + Duration::hours(1))).earliest(),
+    }
+}
+
+pub struct AlarmClock {
+    alarms: Vec<Alarm>,
+    // alarms computed from the agenda, replaced on each refresh
+    agenda: Vec<Alarm>,
+    days_off: DaysOff,
+    // global switch, the time button
+    enabled: bool,
+    state: AlarmState,
+    snoozes: u32,
+    snooze_delay: Duration,
+    max_snoozes: u32,
+    ring_timeout: Duration,
+    ramp_start: u16,
+    ramp: Duration,
+    escalate_after: Option<Duration>,
+    escalation_volume: u16,
+    // volume of alarms without their own volume
+    default_volume: u16,
+    // last ramp volume
+    volume: u16,
+    escalated: bool,
+    dawn: Duration,
+    missed_grace: Duration,
+    missed: Option<DateTime<Local>>,
+    // last occurrence handled by catch_up, it rings or is missed only once
+    caught_up: Option<DateTime<Local>>,
+    // alarms are checked between two ticks
+    last_tick: DateTime<Local>,
+    skipped: Option<DateTime<Local>>,
+    nap: Option<DateTime<Local>>,
+    warm_up: Duration,
+}
+
+impl AlarmClock {
+    pub fn new(alarms: Vec<Alarm>, days_off: DaysOff, config: &AlarmConfig, now: DateTime<Local>) -> Self {
+        AlarmClock {
+            alarms,
+            agenda: Vec::new(),
+            days_off,
+            enabled: true,
+            state: AlarmState::Idle,
+            snoozes: 0,
+            snooze_delay: Duration::minutes(config.snooze_minutes),
+            max_snoozes: config.max_snoozes,
+            ring_timeout: Duration::minutes(config.ring_timeout_minutes),
+            ramp_start: config.ramp_start_volume,
+            ramp: Duration::minutes(config.ramp_minutes),
+            escalate_after: if config.escalate_minutes > 0 { Some(Duration::minutes(config.escalate_minutes)) } else { None },
+            escalation_volume: config.escalation_volume,
+            default_volume: 256,
+            volume: 0,
+            escalated: false,
+            dawn: Duration::minutes(config.dawn_minutes),
+            missed_grace: Duration::minutes(config.missed_grace_minutes),
+            warm_up: Duration::minutes(config.warm_up_minutes),
+            missed: None,
+            caught_up: None,
+            last_tick: now,
+            skipped: None,
+            nap: None,
+        }
+    }
+
+    pub fn set_default_volume(&mut self, volume: u16) {
+        self.default_volume = volume;
+    }
+
+    pub fn overrides(&self) -> Overrides {
+        Overrides { skipped: self.skipped, nap: self.nap, missed: self.missed, alarms_off: !self.enabled }
+    }
+
+    pub fn set_overrides(&mut self, overrides: Overrides) {
+        self.skipped = overrides.skipped;
+        self.nap = overrides.nap;
+        self.missed = overrides.missed;
+        self.enabled = !overrides.alarms_off;
+    }
+
+    pub fn missed(&self) -> Option<DateTime<Local>> {
+        self.missed
+    }
+
+    // the clock was off, or its time jumped, between last seen and started: the last alarm of that time
+    // rings at the next tick if it is not too late, otherwise it is missed
+    // called again when the agenda comes, an occurrence already handled is not looked at again
+    pub fn catch_up(&mut self, last_seen: DateTime<Local>, started: DateTime<Local>, now: DateTime<Local>) {
+        if self.is_active() { return }
+        let mut last = None;
+        let mut after = self.caught_up.map_or(last_seen, |t| t.max(last_seen));
+        while let Some((time, _)) = self.next_alarm(after) {
+            if time > started { break }
+            last = Some(time);
+            after = time;
+        }
+        let time = match last {
+            Some(time) => time,
+            None => return,
+        };
+        println!("Alarm of {} missed while the clock was off", time.format("%Y-%m-%d %H:%M"));
+        self.caught_up = Some(time);
+        if now - time <= self.missed_grace {
+            self.last_tick = time - Duration::seconds(1);
+        } else {
+            self.missed = Some(time);
+            // not to ring at the next tick after a clock jump
+            self.last_tick = self.last_tick.max(time);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // ringing or snoozed
+    pub fn is_active(&self) -> bool {
+        self.state != AlarmState::Idle
+    }
+
+    pub fn set_agenda(&mut self, alarms: Vec<Alarm>) {
+        self.agenda = alarms;
+    }
+
+    // earliest of all alarms, the skipped occurrence excepted
+    pub fn next_alarm(&self, now: DateTime<Local>) -> Option<(DateTime<Local>, &Alarm)> {
+        if !self.enabled { return None }
+        self.alarms.iter().chain(self.agenda.iter())
+            .filter_map(|alarm| {
+                let time = alarm.next_after(now, &self.days_off)?;
+                if Some(time) == self.skipped {
+                    return alarm.next_after(time, &self.days_off).map(|t| (t, alarm));
+                }
+                Some((time, alarm))
+            })
+            .min_by_key(|(t, _)| *t)
+    }
+
+    // skip the next alarm, or ring it again when it is already skipped; true when skipping
+    pub fn skip_next(&mut self, now: DateTime<Local>) -> bool {
+        if self.skipped.take().is_some() { return false }
+        self.skipped = self.next_alarm(now).map(|(t, _)| t);
+        self.skipped.is_some()
+    }
+
+    // ring after some time, or forget the nap
+    pub fn set_nap(&mut self, end: Option<DateTime<Local>>) {
+        self.nap = end;
+    }
+
+    // progress of the dawn before the next alarm from 0 to 1, full while the alarm is on
+    pub fn dawn(&self, now: DateTime<Local>) -> Option<f64> {
+        if self.dawn <= Duration::zero() { return None }
+        if self.is_active() { return Some(1.) }
+        let (next, _) = self.next_alarm(now)?;
+        let left = next - now;
+        if left > self.dawn { return None }
+        Some(1. - left.num_seconds() as f64 / self.dawn.num_seconds() as f64)
+    }
+
+    // sound of the alarm or nap coming soon enough to warm the player up
+    pub fn warm_up(&self, now: DateTime<Local>) -> Option<Sound> {
+        if self.warm_up <= Duration::zero() || self.is_active() { return None }
+        self.coming(now, self.warm_up)
+    }
+
+    // the sound of the nap or the alarm ringing within this time
+    pub fn coming(&self, now: DateTime<Local>, within: Duration) -> Option<Sound> {
+        if self.nap.is_some_and(|t| t > now && t - now <= within) {
+            return Some(Sound { station: None, volume: None });
+        }
+        let (next, alarm) = self.next_alarm(now)?;
+        if next - now > within { return None }
+        Some(alarm.sound())
+    }
+
+    pub fn indicator(&self, now: DateTime<Local>) -> Indicator {
+        match self.state {
+            AlarmState::Ringing { .. } => Indicator::Ringing,
+            AlarmState::Snoozed { .. } => Indicator::Snoozed,
+            // only alarms of the next 24h are worth showing
+            AlarmState::Idle if self.skipped.is_some() => Indicator::Skipped,
+            AlarmState::Idle => match self.next_alarm(now) {
+                Some((next, _)) if next - now <= Duration::hours(24) => Indicator::Armed,
+                _ => Indicator::None,
+            },
+        }
+    }
+
+    pub fn tick(&mut self, now: DateTime<Local>) -> Vec<AlarmEvent> {
+        // alarms jumped over ring only within the grace time, like when the clock was off
+        if now - self.last_tick > Duration::seconds(CLOCK_JUMP_SECONDS) {
+            self.catch_up(self.last_tick, now, now);
+        }
+        let last_tick = self.last_tick;
+        self.last_tick = now;
+        let events = self.alarm_tick(last_tick, now);
+        // a skipped occurrence is skipped once
+        if self.skipped.is_some_and(|t| t <= now) {
+            self.skipped = None;
+        }
+        // a nap is over even when we are already awake
+        if self.nap.is_some_and(|t| t <= now) {
+            self.nap = None;
+            if self.state == AlarmState::Idle {
+                self.snoozes = 0;
+                return self.ring(now, Sound { station: None, volume: None });
+            }
+        }
+        events
+    }
+
+    fn alarm_tick(&mut self, last_tick: DateTime<Local>, now: DateTime<Local>) -> Vec<AlarmEvent> {
+        match self.state {
+            AlarmState::Idle => match self.next_alarm(last_tick) {
+                Some((time, alarm)) if time <= now => {
+                    let sound = alarm.sound();
+                    self.snoozes = 0;
+                    self.ring(now, sound)
+                },
+                _ => Vec::new(),
+            },
+            AlarmState::Ringing { since, .. } if now - since >= self.ring_timeout => {
+                // nobody is listening
+                self.state = AlarmState::Idle;
+                vec![AlarmEvent::Silence]
+            },
+            AlarmState::Ringing { since, .. }
+                if !self.escalated && self.escalate_after.is_some_and(|d| now - since >= d) => {
+                self.escalated = true;
+                vec![AlarmEvent::Escalate(self.escalation_volume)]
+            },
+            AlarmState::Ringing { since, sound } if !self.escalated => {
+                let volume = self.ramp_volume(now - since, sound);
+                if volume == self.volume { return Vec::new() }
+                self.volume = volume;
+                vec![AlarmEvent::Volume(volume)]
+            },
+            AlarmState::Snoozed { until, sound } if now >= until => self.ring(now, sound),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn snooze(&mut self, now: DateTime<Local>) -> Vec<AlarmEvent> {
+        match self.state {
+            // after too many snoozes, it's time to get up
+            AlarmState::Ringing { sound, .. } if self.snoozes < self.max_snoozes => {
+                self.snoozes += 1;
+                self.state = AlarmState::Snoozed { until: now + self.snooze_delay, sound };
+                vec![AlarmEvent::Silence]
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    // dismissing also acknowledges a missed alarm
+    pub fn dismiss(&mut self) -> Vec<AlarmEvent> {
+        self.missed = None;
+        let state = self.state;
+        self.state = AlarmState::Idle;
+        match state {
+            AlarmState::Ringing { .. } => vec![AlarmEvent::Silence],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn toggle(&mut self) -> Vec<AlarmEvent> {
+        self.enabled = !self.enabled;
+        if self.enabled { Vec::new() } else { self.dismiss() }
+    }
+
+    fn ring(&mut self, now: DateTime<Local>, sound: Sound) -> Vec<AlarmEvent> {
+        self.state = AlarmState::Ringing { since: now, sound };
+        self.escalated = false;
+        self.volume = self.ramp_volume(Duration::zero(), sound);
+        vec![AlarmEvent::Ring(Sound { volume: Some(self.volume), ..sound })]
+    }
+
+    // linear from the start volume to the volume of the alarm
+    fn ramp_volume(&self, elapsed: Duration, sound: Sound) -> u16 {
+        let target = sound.volume.unwrap_or(self.default_volume);
+        let start = self.ramp_start.min(target);
+        if elapsed >= self.ramp { return target }
+        start + ((target - start) as i64 * elapsed.num_seconds() / self.ramp.num_seconds()) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn daily(hour: u32) -> Alarm {
+        Alarm {
+            time: NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+            recurrence: Recurrence::Daily,
+            enabled: true,
+            skip_days_off: true,
+            station: None,
+            volume: None,
+        }
+    }
+
+    fn at(day: u32, hour: u32, min: u32, sec: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 3, day, hour, min, sec).unwrap()
+    }
+
+    fn clock(config: AlarmConfig, now: DateTime<Local>) -> AlarmClock {
+        AlarmClock::new(vec![daily(7)], DaysOff::default(), &config, now)
+    }
+
+    fn flat() -> AlarmConfig {
+        AlarmConfig { max_snoozes: 1, ramp_minutes: 0, escalate_minutes: 0, ..Default::default() }
+    }
+
+    fn ring() -> AlarmEvent {
+        AlarmEvent::Ring(Sound { station: None, volume: Some(256) })
+    }
+
+    #[test]
+    fn ring_snooze_timeout() {
+        let start = at(10, 6, 59, 30);
+        let mut alarms = clock(flat(), start);
+        assert_eq!(alarms.indicator(start), Indicator::Armed);
+        assert!(alarms.tick(start + Duration::seconds(20)).is_empty());
+        assert_eq!(alarms.tick(start + Duration::seconds(31)), vec![ring()]);
+        assert!(alarms.tick(start + Duration::seconds(32)).is_empty());
+        assert_eq!(alarms.snooze(start + Duration::seconds(40)), vec![AlarmEvent::Silence]);
+        assert_eq!(alarms.indicator(start), Indicator::Snoozed);
+        assert!(alarms.tick(start + Duration::minutes(5)).is_empty());
+        assert_eq!(alarms.tick(start + Duration::minutes(10)), vec![ring()]);
+        // no snooze left
+        assert!(alarms.snooze(start + Duration::minutes(10)).is_empty());
+        assert_eq!(alarms.tick(start + Duration::minutes(41)), vec![AlarmEvent::Silence]);
+        assert!(alarms.tick(start + Duration::minutes(42)).is_empty());
+        assert_eq!(alarms.indicator(start + Duration::minutes(42)), Indicator::Armed);
+    }
+
+    // local time kept over a year, summer time included
+    #[test]
+    fn whole_year() {
+        let alarm = Alarm { skip_days_off: false, ..daily(7) };
+        let mut time = Local.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+        for _ in 0..365 {
+            let next = alarm.next_after(time, &DaysOff::default()).unwrap();
+            assert_eq!(next.time(), alarm.time);
+            assert!(next - time <= Duration::hours(25));
+            time = next;
+        }
+        assert_eq!(time.date_naive(), chrono::NaiveDate::from_ymd_opt(2027, 1, 1).unwrap());
+    }
+
+    #[test]
+    fn days_off() {
+        let config: crate::config::DaysOffConfig = toml::from_str(r#"
+            vacations = [{ from = "2026-12-21", to = "2027-01-03" }]
+        "#).unwrap();
+        let days_off = DaysOff::new(&config);
+        let now = Local.with_ymd_and_hms(2026, 12, 20, 8, 0, 0).unwrap();
+        let date = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(daily(7).next_after(now, &days_off).unwrap().date_naive(), date(2027, 1, 4));
+        let always = Alarm { skip_days_off: false, ..daily(7) };
+        assert_eq!(always.next_after(now, &days_off).unwrap().date_naive(), date(2026, 12, 21));
+    }
+
+    #[test]
+    fn skip_next() {
+        let start = at(10, 6, 0, 0);
+        let mut alarms = clock(flat(), start);
+        assert!(alarms.skip_next(start));
+        assert_eq!(alarms.indicator(start), Indicator::Skipped);
+        assert_eq!(alarms.next_alarm(start).map(|(t, _)| t), Some(at(11, 7, 0, 0)));
+        let mut time = start;
+        for _ in 0..120 {
+            time += Duration::minutes(1);
+            assert!(alarms.tick(time).is_empty());
+        }
+        // forgotten once passed
+        assert_eq!(alarms.indicator(time), Indicator::Armed);
+        assert_eq!(alarms.overrides().skipped, None);
+        // another press rings it again
+        assert!(alarms.skip_next(time));
+        assert!(!alarms.skip_next(time));
+        assert_eq!(alarms.indicator(time), Indicator::Armed);
+    }
+
+    #[test]
+    fn nap() {
+        let start = at(10, 13, 0, 0);
+        let mut alarms = clock(flat(), start);
+        // even with alarms off
+        alarms.toggle();
+        alarms.set_nap(Some(start + Duration::minutes(20)));
+        assert!(alarms.tick(start + Duration::minutes(19)).is_empty());
+        assert_eq!(alarms.tick(start + Duration::minutes(20)), vec![ring()]);
+        assert_eq!(alarms.indicator(start + Duration::minutes(20)), Indicator::Ringing);
+        assert_eq!(alarms.overrides().nap, None);
+        // cancelled
+        alarms.dismiss();
+        alarms.set_nap(Some(start + Duration::minutes(40)));
+        alarms.set_nap(None);
+        assert!(alarms.tick(start + Duration::minutes(41)).is_empty());
+    }
+
+    #[test]
+    fn ramp_then_escalate() {
+        let start = at(10, 6, 59, 59);
+        let alarm = Alarm { station: Some(0), volume: Some(320), ..daily(7) };
+        let mut alarms = AlarmClock::new(vec![alarm], DaysOff::default(), &AlarmConfig::default(), start);
+        let mut events = Vec::new();
+        for s in 1..=12 * 60 {
+            let time = start + Duration::seconds(s);
+            events.extend(alarms.tick(time).into_iter().map(|e| (time, e)));
+        }
+        assert_eq!(events[0], (at(10, 7, 0, 0), AlarmEvent::Ring(Sound { station: Some(0), volume: Some(64) })));
+        // louder and louder
+        let volumes: Vec<u16> = events.iter().filter_map(|(_, e)| match e { AlarmEvent::Volume(v) => Some(*v), _ => None }).collect();
+        assert!(volumes.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(volumes.last(), Some(&320));
+        assert_eq!(events.last(), Some(&(at(10, 7, 10, 0), AlarmEvent::Escalate(384))));
+    }
+
+    #[test]
+    fn quiet_alarm() {
+        let start = at(10, 6, 59, 59);
+        let alarm = Alarm { volume: Some(10), ..daily(7) };
+        let mut alarms = AlarmClock::new(vec![alarm], DaysOff::default(), &AlarmConfig::default(), start);
+        assert_eq!(alarms.tick(start + Duration::seconds(1)), vec![AlarmEvent::Ring(Sound { station: None, volume: Some(10) })]);
+        assert!(alarms.tick(start + Duration::seconds(100)).is_empty());
+    }
+
+    #[test]
+    fn dawn() {
+        let start = at(10, 6, 20, 0);
+        let mut alarms = clock(AlarmConfig::default(), start);
+        assert_eq!(alarms.dawn(start), None);
+        assert_eq!(alarms.dawn(at(10, 6, 30, 0)), Some(0.));
+        assert_eq!(alarms.dawn(at(10, 6, 45, 0)), Some(0.5));
+        // full light while ringing, off once dismissed
+        alarms.tick(at(10, 7, 0, 0));
+        assert_eq!(alarms.dawn(at(10, 7, 0, 0)), Some(1.));
+        alarms.dismiss();
+        assert_eq!(alarms.dawn(at(10, 7, 1, 0)), None);
+        // no dawn for a skipped alarm
+        assert!(alarms.skip_next(at(11, 6, 30, 0)));
+        assert_eq!(alarms.dawn(at(11, 6, 45, 0)), None);
+    }
+
+    #[test]
+    fn missed() {
+        // off from 6:50 to 7:30, within the grace time
+        let started = at(10, 7, 30, 0);
+        let mut alarms = clock(AlarmConfig::default(), started);
+        alarms.catch_up(at(10, 6, 50, 0), started, started);
+        assert!(matches!(alarms.tick(started + Duration::seconds(1))[..], [AlarmEvent::Ring(_)]));
+        assert_eq!(alarms.missed(), None);
+
+        // off for two days, too late
+        let started = at(10, 9, 30, 0);
+        let mut alarms = clock(AlarmConfig::default(), started);
+        alarms.catch_up(at(8, 6, 50, 0), started, started);
+        assert!(alarms.tick(started + Duration::seconds(1)).is_empty());
+        assert_eq!(alarms.missed(), Some(at(10, 7, 0, 0)));
+        alarms.dismiss();
+        assert_eq!(alarms.missed(), None);
+
+        // nothing missed
+        let mut alarms = clock(AlarmConfig::default(), started);
+        alarms.catch_up(at(10, 7, 10, 0), started, started);
+        assert!(alarms.tick(started + Duration::seconds(1)).is_empty());
+        assert_eq!(alarms.missed(), None);
+    }
+
+    // looked at again when the agenda comes, after the late alarm rang
+    #[test]
+    fn missed_once() {
+        let (last_seen, started) = (at(10, 6, 30, 0), at(10, 7, 5, 0));
+        let mut alarms = clock(flat(), started);
+        alarms.catch_up(last_seen, started, started);
+        assert_eq!(alarms.tick(at(10, 7, 5, 1)), vec![ring()]);
+        alarms.dismiss();
+        alarms.set_agenda(Vec::new());
+        alarms.catch_up(last_seen, started, at(10, 7, 6, 0));
+        for s in 1..600 {
+            assert!(alarms.tick(at(10, 7, 6, 0) + Duration::seconds(s)).is_empty());
+        }
+
+        // too late, dismissed before the agenda comes
+        let (last_seen, started) = (at(9, 6, 30, 0), at(10, 9, 0, 0));
+        let mut alarms = clock(flat(), started);
+        alarms.catch_up(last_seen, started, started);
+        alarms.dismiss();
+        alarms.catch_up(last_seen, started, at(10, 9, 1, 0));
+        assert_eq!(alarms.missed(), None);
+
+        // an agenda alarm missed too, the latest one counts
+        let (last_seen, started) = (at(10, 6, 30, 0), at(10, 7, 40, 0));
+        let mut alarms = clock(flat(), started);
+        alarms.catch_up(last_seen, started, started);
+        alarms.set_agenda(vec![Alarm { time: NaiveTime::from_hms_opt(7, 30, 0).unwrap(), ..daily(7) }]);
+        alarms.catch_up(last_seen, started, started);
+        assert_eq!(alarms.tick(at(10, 7, 40, 1)), vec![ring()]);
+    }
+
+    #[test]
+    fn clock_jump() {
+        // set two hours forward at 6:59, too late
+        let start = at(10, 6, 59, 0);
+        let mut alarms = clock(flat(), start);
+        assert!(alarms.tick(start + Duration::seconds(1)).is_empty());
+        assert!(alarms.tick(at(10, 9, 0, 0)).is_empty());
+        assert_eq!(alarms.missed(), Some(at(10, 7, 0, 0)));
+        assert!(alarms.tick(at(10, 9, 0, 1)).is_empty());
+
+        // set half an hour forward, within the grace time
+        let mut alarms = clock(flat(), start);
+        assert_eq!(alarms.tick(at(10, 7, 30, 0)), vec![ring()]);
+        assert_eq!(alarms.missed(), None);
+    }
+
+    #[test]
+    fn dismiss_and_toggle() {
+        let start = at(10, 6, 59, 30);
+        let mut alarms = clock(flat(), start);
+        assert_eq!(alarms.tick(at(10, 7, 0, 1)), vec![ring()]);
+        assert_eq!(alarms.dismiss(), vec![AlarmEvent::Silence]);
+        assert!(!alarms.is_active());
+        assert_eq!(alarms.next_alarm(at(10, 7, 0, 2)).map(|(t, _)| t), Some(at(11, 7, 0, 0)));
+
+        assert!(alarms.toggle().is_empty());
+        assert!(!alarms.is_enabled());
+        assert_eq!(alarms.indicator(at(10, 8, 0, 0)), Indicator::None);
+        assert!(alarms.tick(at(11, 7, 0, 1)).is_empty());
+        alarms.toggle();
+        assert!(alarms.is_enabled());
+    }
+
+    #[test]
+    fn toggle_is_kept() {
+        let start = at(10, 6, 0, 0);
+        let mut alarms = clock(flat(), start);
+        alarms.toggle();
+        assert!(alarms.overrides().alarms_off);
+        let mut restarted = clock(flat(), start);
+        restarted.set_overrides(alarms.overrides());
+        assert!(!restarted.is_enabled());
+        assert!(restarted.next_alarm(start).is_none());
+    }
+}
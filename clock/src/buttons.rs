@@ -0,0 +1,141 @@
+use rppal::gpio::*;
+use std::io;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::*;
+
+use crate::input::*;
+use crate::keys::Button;
+
+/* One button per pin
+ *
+ *           __o__
+ *  pin -----*   *----- GND     (internal pull-up)
+ *
+ * rppal only lets one thread wait on synchronous interrupts at a time
+ * and Keys is already waiting there, so we use asynchronous interrupts
+ * that forward edges to a channel.
+ */
+
+pub struct GpioButtons {
+    // pins must be kept alive for their interrupts to fire
+    _pins: Vec<InputPin>,
+    buttons: Vec<Button>,
+    events: Receiver<(usize, Event)>,
+    debounce: Duration,
+    // timestamp of the last accepted edge for each button
+    last_edge: Vec<Option<Duration>>,
+    // timestamp of the push for each button currently down
+    pushed: Vec<Option<Duration>>,
+}
+
+impl GpioButtons {
+    pub fn new(gpio: &Gpio, buttons: &[(u8, Button)], debounce: Duration) -> Result<Self> {
+        let (tx, events) = channel();
+        let mut pins = Vec::new();
+        for (index, (number, _)) in buttons.iter().enumerate() {
+            let mut pin = gpio.get(*number)?.into_input_pullup();
+            let tx = tx.clone();
+            pin.set_async_interrupt(Trigger::Both, None, move |event| {
+                let _ = tx.send((index, event));
+            })?;
+            pins.push(pin);
+        }
+        Ok(GpioButtons {
+            _pins: pins,
+            buttons: buttons.iter().map(|(_, b)| *b).collect(),
+            events,
+            debounce,
+            last_edge: vec![None; buttons.len()],
+            pushed: vec![None; buttons.len()],
+        })
+    }
+}
+
+impl Input for GpioButtons {
+    fn poll_press(&mut self) -> io::Result<Option<Press>> {
+        let (index, event) = self.events.recv().map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        // contacts bounce for a few ms, any edge too close to the previous one is noise
+        let bouncing = match self.last_edge[index] {
+            Some(last) => event.timestamp.saturating_sub(last) < self.debounce,
+            None => false,
+        };
+        if bouncing { return Ok(None) }
+        self.last_edge[index] = Some(event.timestamp);
+        // pushed button pulls the pin down, the press is complete on release
+        if event.trigger == Trigger::FallingEdge {
+            self.pushed[index] = Some(event.timestamp);
+            return Ok(None);
+        }
+        Ok(self.pushed[index].take().map(|pushed| Press {
+            button: self.buttons[index],
+            gesture: Gesture::from_duration(event.timestamp.saturating_sub(pushed).as_millis()),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    fn buttons() -> (GpioButtons, Sender<(usize, Event)>) {
+        let (tx, events) = channel();
+        let buttons = GpioButtons {
+            _pins: Vec::new(),
+            buttons: vec![Button::Snooze, Button::OnOff],
+            events,
+            debounce: Duration::from_millis(20),
+            last_edge: vec![None; 2],
+            pushed: vec![None; 2],
+        };
+        (buttons, tx)
+    }
+
+    fn edge(index: usize, ms: u64, trigger: Trigger) -> (usize, Event) {
+        (index, Event { timestamp: Duration::from_millis(ms), seqno: 0, trigger })
+    }
+
+    // presses given by a list of edges
+    fn presses(edges: &[(usize, Event)]) -> Vec<Press> {
+        let (mut buttons, tx) = buttons();
+        for e in edges {
+            tx.send(*e).unwrap();
+        }
+        drop(tx);
+        let mut presses = Vec::new();
+        while let Ok(press) = buttons.poll_press() {
+            presses.extend(press);
+        }
+        presses
+    }
+
+    #[test]
+    fn debounce() {
+        let presses = presses(&[
+            edge(0, 1000, Trigger::FallingEdge),
+            // bounces of the push and of the release
+            edge(0, 1005, Trigger::RisingEdge),
+            edge(0, 1010, Trigger::FallingEdge),
+            edge(0, 1200, Trigger::RisingEdge),
+            edge(0, 1210, Trigger::FallingEdge),
+            edge(0, 1215, Trigger::RisingEdge),
+        ]);
+        assert_eq!(presses, vec![Press { button: Button::Snooze, gesture: Gesture::Short }]);
+    }
+
+    #[test]
+    fn long_press() {
+        let presses = presses(&[
+            edge(1, 1000, Trigger::FallingEdge),
+            edge(0, 1100, Trigger::FallingEdge),
+            edge(0, 1300, Trigger::RisingEdge),
+            edge(1, 2000, Trigger::RisingEdge),
+            // a release without a push is not a press
+            edge(0, 3000, Trigger::RisingEdge),
+        ]);
+        assert_eq!(presses, vec![
+            Press { button: Button::Snooze, gesture: Gesture::Short },
+            Press { button: Button::OnOff, gesture: Gesture::Long },
+        ]);
+    }
+}
@@ -0,0 +1,260 @@
+use base64::Engine;
+use chrono::*;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration as StdDuration;
+
+use crate::config::CalDavConfig;
+use crate::files::write_atomic;
+use crate::ical::events_between;
+
+/* CalDAV client, fetches events of a calendar collection
+ *
+ *   REPORT <collection url>
+ *   Depth: 1
+ *   <calendar-query> with a time range on VEVENT
+ *
+ * The server answers with a multistatus where each response contains
+ * a calendar-data element with a full iCalendar object.
+ *
+ * The last good answer is kept on disk: a network outage must never cancel tomorrow's alarm.
+ * An empty answer doesn't replace a cache with events coming, a server glitch looks the same.
+ */
+
+const CACHE_FILE: &str = "caldav.ics";
+
+pub struct CalDav {
+    url: String,
+    user: Option<String>,
+    password: Option<String>,
+    agent: ureq::Agent,
+    cache: PathBuf,
+}
+
+impl CalDav {
+    pub fn new(config: &CalDavConfig, state_dir: &str) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .allow_non_standard_methods(true)
+            .timeout_global(Some(StdDuration::from_secs(config.timeout_s)))
+            .build()
+            .into();
+        CalDav {
+            url: config.url.clone(),
+            user: config.user.clone(),
+            password: config.password.clone(),
+            agent,
+            cache: PathBuf::from(state_dir).join(CACHE_FILE),
+        }
+    }
+
+    // iCalendar content of events between from and to, from the server or from the cache
+    pub fn calendar(&self, from: DateTime<Local>, to: DateTime<Local>) -> io::Result<String> {
+        match self.fetch(from, to) {
+            Ok(content) => {
+                if content.is_empty() {
+                    if let Ok(cached) = fs::read_to_string(&self.cache) {
+                        if !events_between(&cached, from, to).is_empty() {
+                            println!("WARNING: calendar {} is empty but the cache has events coming, keeping the cache", self.url);
+                            return Ok(cached);
+                        }
+                    }
+                }
+                if let Err(e) = self.save_cache(&content) {
+                    println!("Cannot write caldav cache {:?}", e);
+                }
+                Ok(content)
+            },
+            Err(e) => {
+                println!("Cannot fetch calendar {}: {:?}, using cache", self.url, e);
+                fs::read_to_string(&self.cache)
+            },
+        }
+    }
+
+    fn fetch(&self, from: DateTime<Local>, to: DateTime<Local>) -> io::Result<String> {
+        let mut request = ureq::http::Request::builder()
+            .method("REPORT")
+            .uri(&self.url)
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml; charset=utf-8");
+        if let Some(ref user) = self.user {
+            let credentials = format!("{}:{}", user, self.password.as_deref().unwrap_or(""));
+            let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+            request = request.header("Authorization", format!("Basic {}", encoded));
+        }
+        let request = request.body(query(from, to)).map_err(io::Error::other)?;
+        let mut response = self.agent.run(request).map_err(io::Error::other)?;
+        let body = response.body_mut().read_to_string().map_err(io::Error::other)?;
+        Ok(calendar_data(&body).join("\n"))
+    }
+
+    // a half written cache is worse than an old one
+    fn save_cache(&self, content: &str) -> io::Result<()> {
+        write_atomic(&self.cache, content.as_bytes())
+    }
+}
+
+fn query(from: DateTime<Local>, to: DateTime<Local>) -> String {
+    let format = "%Y%m%dT%H%M%SZ";
+    format!(r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop>
+    <C:calendar-data/>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{}" end="{}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>
+"#, from.with_timezone(&Utc).format(format), to.with_timezone(&Utc).format(format))
+}
+
+// content of every calendar-data element, whatever its namespace prefix
+pub fn calendar_data(xml: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("calendar-data") {
+        // find the opening tag: <prefix:calendar-data ...>, skip closing and empty tags
+        let tag_start = match rest[..start].rfind('<') {
+            Some(i) => i,
+            None => { rest = &rest[start + 1..]; continue },
+        };
+        let tag_end = match rest[start..].find('>') {
+            Some(i) => start + i,
+            None => break,
+        };
+        let tag = &rest[tag_start..=tag_end];
+        if tag.starts_with("</") || tag.ends_with("/>") {
+            rest = &rest[tag_end + 1..];
+            continue;
+        }
+        let name = &rest[tag_start + 1..start + "calendar-data".len()];
+        let closing = format!("</{}>", name);
+        let content_end = match rest[tag_end..].find(&closing) {
+            Some(i) => tag_end + i,
+            None => break,
+        };
+        result.push(unescape_xml(&rest[tag_end + 1..content_end]));
+        rest = &rest[content_end + closing.len()..];
+    }
+    result
+}
+
+fn unescape_xml(text: &str) -> String {
+    let text = text.trim();
+    if let Some(cdata) = text.strip_prefix("<![CDATA[").and_then(|t| t.strip_suffix("]]>")) {
+        return cdata.to_string();
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&#xD;", "\r")
+        .replace("&#10;", "\n")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const ANSWER: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+<d:response><d:propstat><d:prop><cal:calendar-data>BEGIN:VCALENDAR&#13;
+SUMMARY:a &amp; b
+END:VCALENDAR</cal:calendar-data></d:prop></d:propstat></d:response>
+<d:response><d:prop><C:calendar-data xmlns:C="x"><![CDATA[BEGIN:VCALENDAR
+END:VCALENDAR]]></C:calendar-data></d:prop></d:response>
+<d:response><d:prop><cal:calendar-data/></d:prop></d:response>
+</d:multistatus>"#;
+
+    #[test]
+    fn multistatus() {
+        assert_eq!(calendar_data(ANSWER), vec![
+            "BEGIN:VCALENDAR\r\nSUMMARY:a & b\nEND:VCALENDAR",
+            "BEGIN:VCALENDAR\nEND:VCALENDAR",
+        ]);
+        assert!(calendar_data("<d:multistatus/>").is_empty());
+    }
+
+    // answers one request, returns its head
+    fn server(answer: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/calendars/me/", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() { break }
+                head.push_str(&line);
+            }
+            let length = head.lines()
+                .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|n| n.trim().parse().unwrap()))
+                .unwrap_or(0);
+            reader.take(length).read_to_end(&mut Vec::new()).unwrap();
+            write!(socket, "HTTP/1.1 207 Multi-Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   answer.len(), answer).unwrap();
+            head
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn fetch_then_cache() {
+        let state_dir = std::env::temp_dir().join("clock-caldav-test");
+        let _ = fs::remove_dir_all(&state_dir);
+        let (url, handle) = server(ANSWER);
+        let config = CalDavConfig { url, user: Some("me".to_string()), password: Some("secret".to_string()), timeout_s: 5 };
+        let caldav = CalDav::new(&config, state_dir.to_str().unwrap());
+        let now = Local::now();
+        let calendar = caldav.calendar(now, now + Duration::days(1)).unwrap();
+        assert!(calendar.contains("SUMMARY:a & b"));
+        let head = handle.join().unwrap();
+        assert!(head.starts_with("REPORT /calendars/me/ HTTP/1.1"));
+        assert!(head.to_lowercase().contains("depth: 1"));
+        // me:secret
+        assert!(head.contains("Basic bWU6c2VjcmV0"));
+
+        // the server is gone
+        assert_eq!(caldav.calendar(now, now + Duration::days(1)).unwrap(), calendar);
+        fs::remove_dir_all(&state_dir).unwrap();
+        assert!(caldav.calendar(now, now + Duration::days(1)).is_err());
+    }
+
+    #[test]
+    fn empty_answer_keeps_cache() {
+        let state_dir = std::env::temp_dir().join("clock-caldav-empty-test");
+        let _ = fs::remove_dir_all(&state_dir);
+        let now = Local::now();
+        let start = (now + Duration::days(1)).with_timezone(&Utc).format("%Y%m%dT%H%M%SZ");
+        let cached = format!("BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:{}\nSUMMARY:train\nEND:VEVENT\nEND:VCALENDAR\n", start);
+        fs::create_dir_all(&state_dir).unwrap();
+        fs::write(state_dir.join(CACHE_FILE), &cached).unwrap();
+        let (url, handle) = server("<d:multistatus xmlns:d=\"DAV:\"/>");
+        let config = CalDavConfig { url, user: None, password: None, timeout_s: 5 };
+        let caldav = CalDav::new(&config, state_dir.to_str().unwrap());
+        assert_eq!(caldav.calendar(now, now + Duration::days(2)).unwrap(), cached);
+        handle.join().unwrap();
+        assert_eq!(fs::read_to_string(state_dir.join(CACHE_FILE)).unwrap(), cached);
+
+        // nothing coming in the cache, the empty calendar is believed
+        let (url, handle) = server("<d:multistatus xmlns:d=\"DAV:\"/>");
+        let config = CalDavConfig { url, user: None, password: None, timeout_s: 5 };
+        let caldav = CalDav::new(&config, state_dir.to_str().unwrap());
+        let later = now + Duration::days(3);
+        assert_eq!(caldav.calendar(later, later + Duration::days(2)).unwrap(), "");
+        handle.join().unwrap();
+        fs::remove_dir_all(&state_dir).unwrap();
+    }
+}
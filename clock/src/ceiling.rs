@@ -34,8 +34,18 @@ const BYTE2: [u8; 12] = [1,0,0,0,0,0,0,0,0,0,1,0];
 const BYTE3: [u8; 12] = [1,0,0,0,0,0,0,0,0,1,1,0];
 const BYTE4: [u8; 9] = [1,0,1,0,1,1,0,0,0];
 
+// duty cycle for a perceived lightness from 0 to 1 (CIE 1931), eyes are not linear
+fn luminance(lightness: f64) -> f64 {
+    let l = lightness.clamp(0., 1.) * 100.;
+    if l <= 8. {
+        l / 903.3
+    } else {
+        ((l + 16.) / 116.).powi(3)
+    }
+}
+
 pub struct Ceiling {
-    led: OutputPin, 
+    led: OutputPin,
     data: OutputPin,
     clock: OutputPin,
     line: OutputPin,
@@ -73,13 +83,17 @@ impl Ceiling {
         self.write_sequence(&data);
     }
 
-    pub fn set_light(&mut self) {
-        println!("remove");
+    // the dawn wins over the settings, even when the ceiling is off
+    pub fn set_light(&mut self) -> Result<()> {
         self.led.set_high();
         let ddt = self.display_data.lock().expect("poisoned mutex 2");
-        let level = ddt.ceiling_dim as f64;
+        let level = match ddt.dawn {
+            Some(progress) => luminance(progress) * 100.,
+            None if ddt.ceiling_enabled => ddt.ceiling_dim as f64,
+            None => 0.,
+        };
         let frequency = ddt.refresh_rate as f64;
-        self.led.set_pwm_frequency(frequency, level/100.);
+        self.led.set_pwm_frequency(frequency, level/100.)
     }
 
     fn write_sequence(&mut self, bits: &[u8]) {
@@ -1,4 +1,4 @@
-use crate::player::Player;
+use std::time::*;
 
 /* On main led 7 segments
  *    0
@@ -22,6 +22,123 @@ const PINS_8: [u8; 7] = [1, 1, 1, 1, 1, 1, 1];
 const PINS_9: [u8; 7] = [1, 1, 1, 1, 1, 0, 1];
 const PINS_X: [u8; 7] = [1, 1, 0, 1, 0, 1, 1];
 
+// letters, as close as 7 segments allow, case is a hint
+#[rustfmt::skip]
+fn letter_pins(c: char) -> [u8; 7] {
+    match c {
+        'A' | 'a' => [1, 1, 1, 1, 1, 1, 0],
+        'B' | 'b' => [0, 1, 0, 1, 1, 1, 1],
+        'C'       => [1, 1, 0, 0, 0, 1, 1],
+        'c'       => [0, 0, 0, 1, 0, 1, 1],
+        'D' | 'd' => [0, 0, 1, 1, 1, 1, 1],
+        'E' | 'e' => PINS_X,
+        'F' | 'f' => [1, 1, 0, 1, 0, 1, 0],
+        'G' | 'g' => [1, 1, 0, 0, 1, 1, 1],
+        'H'       => [0, 1, 1, 1, 1, 1, 0],
+        'h'       => [0, 1, 0, 1, 1, 1, 0],
+        'I'       => PINS_1,
+        'i'       => [0, 0, 0, 0, 1, 0, 0],
+        'J' | 'j' => [0, 0, 1, 0, 1, 1, 1],
+        'L' | 'l' => [0, 1, 0, 0, 0, 1, 1],
+        'N'       => [1, 1, 1, 0, 1, 1, 0],
+        'n' | 'M' | 'm' => [0, 0, 0, 1, 1, 1, 0],
+        'O'       => PINS_0,
+        'o'       => [0, 0, 0, 1, 1, 1, 1],
+        'P' | 'p' => [1, 1, 1, 1, 0, 1, 0],
+        'Q' | 'q' => [1, 1, 1, 1, 1, 0, 0],
+        'R' | 'r' => [0, 0, 0, 1, 0, 1, 0],
+        'S' | 's' => PINS_5,
+        'T' | 't' => [0, 1, 0, 1, 0, 1, 1],
+        'U' | 'V' => [0, 1, 1, 0, 1, 1, 1],
+        'u' | 'v' => [0, 0, 0, 0, 1, 1, 1],
+        'Y' | 'y' => [0, 1, 1, 1, 1, 0, 1],
+        'Z' | 'z' => PINS_2,
+        '0' => PINS_0,
+        '1' => PINS_1,
+        '2' => PINS_2,
+        '3' => PINS_3,
+        '4' => PINS_4,
+        '5' => PINS_5,
+        '6' => PINS_6,
+        '7' => PINS_7,
+        '8' => PINS_8,
+        '9' => PINS_9,
+        '-' => [0, 0, 0, 1, 0, 0, 0],
+        '_' => [0, 0, 0, 0, 0, 0, 1],
+        _   => [0; 7],
+    }
+}
+
+// error bits, shown on the right indicator
+pub const ERROR_MISSED_ALARM: u8 = 1;
+// the radio stream failed, another station or the fallback file plays
+pub const ERROR_STREAM: u8 = 2;
+// the fallback file is missing
+pub const ERROR_FALLBACK: u8 = 4;
+
+const BLINK_MS: u128 = 500;
+
+// off half of the blinking period
+fn blink_off() -> bool {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_millis() / BLINK_MS) % 2 == 1,
+        Err(_) => false,
+    }
+}
+
+// a scrolling text moves by one character this often
+const SCROLL_STEP: Duration = Duration::from_millis(400);
+
+// text shown on the led display instead of the time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlay {
+    pub text: [char; 4],
+    pub blink: bool,
+    // shown until removed when None
+    pub until: Option<Instant>,
+    // longer text moving to the left, and when it started
+    scroll: Option<(Vec<char>, Instant)>,
+}
+
+impl Overlay {
+    // text is truncated or padded with spaces to 4 characters
+    pub fn new(text: &str, blink: bool, duration: Option<Duration>) -> Self {
+        let mut chars = [' '; 4];
+        for (i, c) in text.chars().take(4).enumerate() {
+            chars[i] = c;
+        }
+        Overlay { text: chars, blink, until: duration.map(|d| Instant::now() + d), scroll: None }
+    }
+
+    // the text comes from the right and leaves on the left, once
+    pub fn scrolling(text: &str) -> Self {
+        let now = Instant::now();
+        let chars: Vec<char> = "   ".chars().chain(text.chars()).collect();
+        let duration = SCROLL_STEP * (chars.len() + 1) as u32;
+        Overlay { text: [' '; 4], blink: false, until: Some(now + duration), scroll: Some((chars, now)) }
+    }
+
+    fn chars(&self, now: Instant) -> [char; 4] {
+        let (text, start) = match self.scroll {
+            Some((ref text, start)) => (text, start),
+            None => return self.text,
+        };
+        let offset = (now.duration_since(start).as_millis() / SCROLL_STEP.as_millis()) as usize;
+        let mut chars = [' '; 4];
+        for (i, c) in text.iter().skip(offset).take(4).enumerate() {
+            chars[i] = *c;
+        }
+        chars
+    }
+
+    fn is_visible(&self, now: Instant) -> bool {
+        match self.until {
+            Some(until) => now < until,
+            None => true,
+        }
+    }
+}
+
 /* On ceiling 7 segments
  *    3
  *   --
@@ -39,12 +156,19 @@ pub struct ClockData {
     pub minutes: u8,
     pub has_alarm: bool,
     pub alarm_enabled: bool,
+    pub alarm_ringing: bool,
+    pub alarm_snoozed: bool,
+    pub alarm_skipped: bool,
+    pub nap: bool,
     pub error: u8,
     pub regular_dim: u8,   // percentage
     pub refresh_rate: u32, // hertz (regular 7 segments and ceiling led)
     pub ceiling_dim: u8,   // percentage
+    pub ceiling_enabled: bool,
     pub ceiling_upwards: bool,
-    pub player: Player,
+    // dawn progress before an alarm, from 0 to 1
+    pub dawn: Option<f64>,
+    pub overlay: Option<Overlay>,
 }
 
 impl ClockData {
@@ -54,21 +178,27 @@ impl ClockData {
             minutes: 88,
             has_alarm: false,
             alarm_enabled: true,
+            alarm_ringing: false,
+            alarm_snoozed: false,
+            alarm_skipped: false,
+            nap: false,
             error: 0,
             regular_dim: 50,
             refresh_rate: 100,
             ceiling_dim: 50,
+            ceiling_enabled: true,
             ceiling_upwards: true,
-            player: Player::new(),
+            dawn: None,
+            overlay: None,
         }
     }
 
     fn get_time_pins(&self, pos: usize) -> [u8; 7] {
         let value = match pos {
-            0 => (self.hours / 10) as u8,
-            1 => (self.hours % 10) as u8,
-            2 => (self.minutes / 10) as u8,
-            3 => (self.minutes % 10) as u8,
+            0 => self.hours / 10,
+            1 => self.hours % 10,
+            2 => self.minutes / 10,
+            3 => self.minutes % 10,
             _ => 10,
         };
         match value {
@@ -96,18 +226,34 @@ impl ClockData {
                 pins[*i] = pin_list[mangle[*i]];
             }
         }
-        return pins;
+        pins
+    }
+
+    // overlay text when there is one, time otherwise
+    fn get_digit_pins(&self, pos: usize) -> [u8; 7] {
+        let now = Instant::now();
+        match self.overlay {
+            Some(ref overlay) if overlay.is_visible(now) => {
+                let hidden = overlay.blink && blink_off();
+                if hidden { [0; 7] } else { letter_pins(overlay.chars(now)[pos]) }
+            },
+            _ => self.get_time_pins(pos),
+        }
     }
 
     pub fn get_row_pins_led(&self, col: usize) -> [u8; 7] {
+        let colon = match self.overlay {
+            Some(ref overlay) if overlay.is_visible(Instant::now()) => [0; 7],
+            _ => [0, 1, 0, 0, 0, 1, 0],
+        };
         match col {
-            0 => self.get_time_pins(0),
+            0 => self.get_digit_pins(0),
             1 => self.left_opts(),
-            2 => self.get_time_pins(1),
-            3 => [0, 1, 0, 0, 0, 1, 0],
-            4 => self.get_time_pins(2),
+            2 => self.get_digit_pins(1),
+            3 => colon,
+            4 => self.get_digit_pins(2),
             5 => self.right_opts(),
-            6 => self.get_time_pins(3),
+            6 => self.get_digit_pins(3),
             _ => [0; 7],
         }
     }
@@ -123,13 +269,22 @@ impl ClockData {
     fn left_opts(&self) -> [u8; 7] {
         let mut result = [0; 7];
         // left opts are alarm related
-        if self.has_alarm {
+        if self.has_alarm || self.alarm_snoozed || (self.alarm_ringing && !blink_off()) {
             result[5] = 1;
         }
+        if self.alarm_snoozed {
+            result[4] = 1;
+        }
+        if self.alarm_skipped {
+            result[3] = 1;
+        }
+        if self.nap {
+            result[2] = 1;
+        }
         if !self.alarm_enabled {
             result[1] = 1;
         }
-        return result;
+        result
     }
 
     #[rustfmt::skip]
@@ -141,7 +296,7 @@ impl ClockData {
         if (err / 2) % 2 == 1 { result[4] = 1; }
         if (err / 4) % 2 == 1 { result[2] = 1; }
         if (err / 8) % 2 == 1 { result[1] = 1; }
-        return result;
+        result
     }
 
     pub fn pwm_time(&self, up: bool) -> u64 {
@@ -150,7 +305,7 @@ impl ClockData {
         } else {
             100 - self.regular_dim as u64
         };
-        return (1_000_000 / self.refresh_rate as u64) * pct / 100;
+        (1_000_000 / self.refresh_rate as u64) * pct / 100
     }
 }
 
@@ -0,0 +1,493 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process::exit;
+
+use crate::action::*;
+use crate::holidays::Country;
+use crate::player::Playback;
+use crate::input::Gesture;
+use crate::keys::Button;
+use crate::schedule::Recurrence;
+use chrono::{NaiveDate, Weekday};
+
+/* Configuration is read once at startup from a toml file
+ *
+ * The file is /etc/clock.toml unless CLOCK_CONFIG tells otherwise.
+ * A missing or broken file is not fatal: the clock must run anyway,
+ * so we print the problem and use the defaults.
+ */
+
+const DEFAULT_PATH: &str = "/etc/clock.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // where the clock keeps what it must remember across restarts
+    pub state_dir: String,
+    pub inputs: Vec<InputConfig>,
+    pub bindings: Vec<BindingConfig>,
+    pub ui: UiConfig,
+    pub alarm: AlarmConfig,
+    pub alarms: Vec<AlarmDefinition>,
+    pub agenda: AgendaConfig,
+    pub days_off: DaysOffConfig,
+    pub player: PlayerConfig,
+    pub radio: RadioConfig,
+    pub timeshift: TimeshiftConfig,
+}
+
+// what plays the sound
+#[derive(Debug, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PlayerConfig {
+    // vlc remote control, vlc is started by the clock
+    #[default]
+    Vlc,
+    // mpv json ipc, mpv is started by the clock
+    Mpv {
+        #[serde(default = "default_mpv_socket")]
+        socket: String,
+    },
+    // music player daemon, running on its own
+    Mpd {
+        #[serde(default = "default_mpd_address")]
+        address: String,
+    },
+    // decoded in the clock process, needs the native feature
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    Native {
+        #[serde(default)]
+        output: NativeOutput,
+        // alsa device
+        #[serde(default = "default_alsa_device")]
+        device: String,
+        // file written by the wav output
+        #[serde(default = "default_wav_path")]
+        path: String,
+    },
+    // no sound, playback states returned by the state queries
+    Mock {
+        #[serde(default)]
+        script: Vec<Playback>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RadioConfig {
+    // played when the radio doesn't wake us up
+    pub fallback: String,
+    pub stations: Vec<StationConfig>,
+    // m3u or pls files or urls, each entry is a station after the configured ones
+    pub playlists: Vec<String>,
+    // a stream not playing for this long has failed
+    pub stall_seconds: i64,
+    // next stations tried before the fallback file
+    pub failovers: usize,
+    // the failed station is tried again after this delay
+    pub retry_minutes: i64,
+}
+
+impl Default for RadioConfig {
+    fn default() -> Self {
+        RadioConfig {
+            fallback: "fallback.mp3".to_string(),
+            stations: Vec::new(),
+            playlists: Vec::new(),
+            stall_seconds: 30,
+            failovers: 2,
+            retry_minutes: 10,
+        }
+    }
+}
+
+// the radio recorded before the alarm, played from the news
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TimeshiftConfig {
+    // where the recording goes, nothing is recorded when missing
+    pub dir: Option<String>,
+    // local http port the players read the recording from
+    pub port: u16,
+    // minutes of the hour the playback starts from, like the news
+    pub marks: Vec<u32>,
+    // recording starts this long before the alarm
+    pub lead_minutes: i64,
+    // the alarm rings live when the last mark is older
+    pub max_delay_minutes: i64,
+    // older recording is removed
+    pub keep_minutes: i64,
+}
+
+impl Default for TimeshiftConfig {
+    fn default() -> Self {
+        TimeshiftConfig {
+            dir: None,
+            port: 8731,
+            marks: vec![0],
+            lead_minutes: 10,
+            max_delay_minutes: 15,
+            keep_minutes: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StationConfig {
+    // the url when missing
+    pub name: Option<String>,
+    // a stream, or a m3u or pls playlist holding it
+    pub url: String,
+    // 4 characters for the display, the start of the name when missing
+    pub label: Option<String>,
+    // added to the volume while this station plays, 256 is 100%
+    #[serde(default)]
+    pub volume_offset: i16,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NativeOutput {
+    #[default]
+    Alsa,
+    // a wav file, to listen to it later
+    Wav,
+    // nothing, at the pace of real time
+    Null,
+}
+
+fn default_alsa_device() -> String { "default".to_string() }
+
+fn default_wav_path() -> String { "/tmp/clock.wav".to_string() }
+
+fn default_mpv_socket() -> String { "/tmp/clock-mpv.sock".to_string() }
+
+fn default_mpd_address() -> String { "localhost:6600".to_string() }
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct DaysOffConfig {
+    // public holidays of this country
+    pub country: Country,
+    pub vacations: Vec<VacationConfig>,
+    // days that are never off
+    pub force: Vec<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VacationConfig {
+    // first and last day of the vacation
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AgendaConfig {
+    // .ics file
+    pub path: Option<String>,
+    // calendar server
+    pub caldav: Option<CalDavConfig>,
+    // time between the alarm and the first event of the day
+    pub preparation_minutes: i64,
+    // how often the agenda is read again
+    pub refresh_minutes: u64,
+    // how many days of alarms are computed
+    pub days: i64,
+    pub rules: RulesConfig,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalDavConfig {
+    // calendar collection
+    pub url: String,
+    // basic authentication
+    pub user: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_timeout_s")]
+    pub timeout_s: u64,
+}
+
+fn default_timeout_s() -> u64 { 30 }
+
+impl Default for AgendaConfig {
+    fn default() -> Self {
+        AgendaConfig {
+            path: None,
+            caldav: None,
+            preparation_minutes: 90,
+            refresh_minutes: 15,
+            days: 7,
+            rules: RulesConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AllDay {
+    // all-day events don't change the alarm
+    #[default]
+    Ignore,
+    // no alarm on a day with an all-day event
+    Skip,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct RulesConfig {
+    // categories of events that never wake up, also written #category in the summary
+    pub ignore: Vec<String>,
+    pub all_day: AllDay,
+    pub commute: Vec<CommuteConfig>,
+    // HH:MM bounds of the wake up time
+    pub earliest: Option<String>,
+    pub latest: Option<String>,
+    // alarm of a day without event
+    pub default: Option<DefaultAlarmConfig>,
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        RulesConfig {
+            ignore: vec!["no-wake".to_string()],
+            all_day: AllDay::Ignore,
+            commute: Vec::new(),
+            earliest: None,
+            latest: None,
+            default: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommuteConfig {
+    // part of the event location, case insensitive
+    pub location: String,
+    pub minutes: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DefaultAlarmConfig {
+    // HH:MM
+    pub time: String,
+    pub weekdays: Vec<Weekday>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AlarmConfig {
+    pub snooze_minutes: i64,
+    // snooze doesn't work anymore after this
+    pub max_snoozes: u32,
+    // stop ringing when nobody reacts
+    pub ring_timeout_minutes: i64,
+    // the alarm starts quietly and gets to its volume after this
+    pub ramp_start_volume: u16,
+    pub ramp_minutes: i64,
+    // play the fallback file when nobody reacts after this, never when 0
+    pub escalate_minutes: i64,
+    pub escalation_volume: u16,
+    // the ceiling light rises during this time before the alarm, never when 0
+    pub dawn_minutes: i64,
+    // an alarm missed while the clock was off rings at startup during this time, later it is an error
+    pub missed_grace_minutes: i64,
+    // the player starts muted this long before the alarm, 0 to start it when ringing
+    pub warm_up_minutes: i64,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        AlarmConfig {
+            snooze_minutes: 9,
+            max_snoozes: 3,
+            ring_timeout_minutes: 30,
+            ramp_start_volume: 64,
+            ramp_minutes: 3,
+            escalate_minutes: 10,
+            escalation_volume: 384,
+            dawn_minutes: 30,
+            missed_grace_minutes: 60,
+            warm_up_minutes: 2,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    // special and settings modes return to normal after this time without any press
+    pub mode_timeout_s: u64,
+    // scroll the program title of the radio when it changes
+    pub scroll_titles: bool,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        UiConfig { mode_timeout_s: 60, scroll_titles: false }
+    }
+}
+
+// every input backend listed here feeds the same button channel
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InputConfig {
+    // resistor ladder buttons of the clock
+    Keys,
+    // linux input device (usb keypad, ir receiver ...), keymap is linux key code -> button
+    Evdev {
+        path: String,
+        #[serde(default)]
+        keymap: HashMap<String, Button>,
+    },
+    // one button per gpio pin (BCM numbering), wired to ground
+    Gpio {
+        buttons: Vec<GpioButtonConfig>,
+        #[serde(default = "default_debounce_ms")]
+        debounce_ms: u64,
+    },
+    // quadrature rotary encoder (BCM numbering), wired to ground
+    Rotary {
+        pin_a: u8,
+        pin_b: u8,
+        #[serde(default = "default_clockwise")]
+        clockwise: Button,
+        #[serde(default = "default_counter_clockwise")]
+        counter_clockwise: Button,
+        #[serde(default = "default_steps")]
+        steps: i8,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GpioButtonConfig {
+    pub pin: u8,
+    pub button: Button,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlarmDefinition {
+    // HH:MM local time
+    pub time: String,
+    #[serde(default)]
+    pub repeat: Recurrence,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    // no alarm on holidays and vacations
+    #[serde(default = "default_enabled")]
+    pub skip_days_off: bool,
+    // index in the station list, current station when missing
+    pub station: Option<usize>,
+    // player volume, default volume when missing
+    pub volume: Option<u16>,
+}
+
+fn default_enabled() -> bool { true }
+
+// replaces the default action of a button
+#[derive(Debug, Deserialize)]
+pub struct BindingConfig {
+    #[serde(default = "default_mode")]
+    pub mode: Mode,
+    pub button: Button,
+    #[serde(default = "default_gesture")]
+    pub gesture: Gesture,
+    pub action: Action,
+}
+
+fn default_mode() -> Mode { Mode::Normal }
+fn default_gesture() -> Gesture { Gesture::Short }
+fn default_debounce_ms() -> u64 { 30 }
+fn default_clockwise() -> Button { Button::SpkrHigh }
+fn default_counter_clockwise() -> Button { Button::SpkrLow }
+// most encoders go through 4 transitions per detent
+fn default_steps() -> i8 { 4 }
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            state_dir: "/var/lib/clock".to_string(),
+            inputs: vec![InputConfig::Keys],
+            bindings: Vec::new(),
+            ui: UiConfig::default(),
+            alarm: AlarmConfig::default(),
+            alarms: Vec::new(),
+            agenda: AgendaConfig::default(),
+            days_off: DaysOffConfig::default(),
+            player: PlayerConfig::default(),
+            radio: RadioConfig::default(),
+            timeshift: TimeshiftConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    // a clock with a typo in its alarms would silently ring at the default times, it doesn't start
+    pub fn load() -> Self {
+        let path = env::var("CLOCK_CONFIG").unwrap_or_else(|_| DEFAULT_PATH.to_string());
+        match Config::read(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Invalid config {}: {}", path, e);
+                exit(1);
+            },
+        }
+    }
+
+    // only a missing file gives the defaults
+    fn read(path: &str) -> Result<Self, toml::de::Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Cannot read config {}: {}, using defaults", path, e);
+                return Ok(Config::default());
+            },
+        };
+        toml::from_str(&content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../clock.toml");
+
+    // options are commented out as #key, explanations as # text
+    fn uncomment(content: &str) -> String {
+        content.lines()
+            .map(|l| if l.starts_with('#') && !l.starts_with("# ") && l.len() > 1 { &l[1..] } else { l })
+            .collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn example() {
+        let config: Config = toml::from_str(EXAMPLE).unwrap();
+        assert!(!config.inputs.is_empty());
+        assert!(!config.radio.stations.is_empty());
+        let config: Config = toml::from_str(&uncomment(EXAMPLE)).unwrap();
+        assert!(config.inputs.len() > 1);
+        assert!(!config.bindings.is_empty());
+    }
+
+    #[test]
+    fn read() {
+        let dir = std::env::temp_dir().join("clock-config-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clock.toml");
+        fs::write(&path, "inputs = 3").unwrap();
+        assert!(Config::read(path.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(Config::read(path.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(matches!(config.inputs[..], [InputConfig::Keys]));
+        assert!(matches!(config.player, PlayerConfig::Vlc));
+        assert!(toml::from_str::<Config>("inputs = 3").is_err());
+    }
+}
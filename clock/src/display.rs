@@ -68,8 +68,8 @@ impl LedDisplay {
     fn show_col(&mut self, col: usize) {
         self.pins_col[col].set_low();
         let pins = self.display_data.lock().expect("poisoned mutex 3").get_row_pins_led(col);
-        for row in 0..7 {
-            if pins[row] == 1 {
+        for (row, pin) in pins.iter().enumerate() {
+            if *pin == 1 {
                 self.pins_row[row].set_high();
             }
         }
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::mem::size_of;
+use std::time::Duration;
+
+use crate::input::*;
+use crate::keys::Button;
+
+/* Linux input device : usb keypads, ir receivers, anything in /dev/input/event*
+ *
+ * struct input_event {
+ *   struct timeval time;  // 2 longs
+ *   __u16 type;
+ *   __u16 code;
+ *   __s32 value;          // 0 release, 1 press, 2 autorepeat
+ * };
+ *
+ * Any reader works, so a recorded event file can be replayed.
+ */
+
+const TIME_SIZE: usize = 2 * WORD_SIZE;
+const EVENT_SIZE: usize = TIME_SIZE + 8;
+const WORD_SIZE: usize = size_of::<usize>();
+const EV_KEY: u16 = 1;
+const RELEASE: i32 = 0;
+const PRESS: i32 = 1;
+
+// linux key code -> button, used when the configuration has no keymap
+#[rustfmt::skip]
+const DEFAULT_KEYMAP: [(u16, Button); 12] = [
+    (105, Button::Left),     // KEY_LEFT
+    (106, Button::Right),    // KEY_RIGHT
+    (114, Button::SpkrLow),  // KEY_VOLUMEDOWN
+    (115, Button::SpkrHigh), // KEY_VOLUMEUP
+    (116, Button::OnOff),    // KEY_POWER
+    (164, Button::OnOff),    // KEY_PLAYPAUSE
+    ( 57, Button::Snooze),   // KEY_SPACE
+    ( 20, Button::Time),     // KEY_T
+    (  2, Button::B1),       // KEY_1
+    (  3, Button::B2),       // KEY_2
+    (407, Button::Right),    // KEY_NEXT
+    (412, Button::Left),     // KEY_PREVIOUS
+];
+
+pub struct Evdev<R: Read + Send> {
+    reader: R,
+    keymap: HashMap<u16, Button>,
+    // event time of the push for each key currently down
+    pushed: HashMap<u16, Duration>,
+}
+
+impl Evdev<File> {
+    pub fn open(path: &str, keymap: &HashMap<u16, Button>) -> io::Result<Self> {
+        Ok(Evdev::new(File::open(path)?, keymap))
+    }
+}
+
+impl<R: Read + Send> Evdev<R> {
+    pub fn new(reader: R, keymap: &HashMap<u16, Button>) -> Self {
+        let keymap = if keymap.is_empty() {
+            DEFAULT_KEYMAP.iter().cloned().collect()
+        } else {
+            keymap.clone()
+        };
+        Evdev { reader, keymap, pushed: HashMap::new() }
+    }
+
+    // (time, type, code, value)
+    fn read_event(&mut self) -> io::Result<(Duration, u16, u16, i32)> {
+        let mut buffer = [0; EVENT_SIZE];
+        self.reader.read_exact(&mut buffer)?;
+        let mut word = [0; WORD_SIZE];
+        word.copy_from_slice(&buffer[0..WORD_SIZE]);
+        let seconds = usize::from_ne_bytes(word) as u64;
+        word.copy_from_slice(&buffer[WORD_SIZE..TIME_SIZE]);
+        let micros = usize::from_ne_bytes(word) as u64;
+        let time = Duration::from_secs(seconds) + Duration::from_micros(micros);
+        let kind = u16::from_ne_bytes([buffer[TIME_SIZE], buffer[TIME_SIZE + 1]]);
+        let code = u16::from_ne_bytes([buffer[TIME_SIZE + 2], buffer[TIME_SIZE + 3]]);
+        let value = i32::from_ne_bytes([buffer[TIME_SIZE + 4], buffer[TIME_SIZE + 5],
+                                        buffer[TIME_SIZE + 6], buffer[TIME_SIZE + 7]]);
+        Ok((time, kind, code, value))
+    }
+}
+
+impl<R: Read + Send> Input for Evdev<R> {
+    fn poll_press(&mut self) -> io::Result<Option<Press>> {
+        loop {
+            let (time, kind, code, value) = self.read_event()?;
+            // ignore sync and autorepeat events
+            if kind != EV_KEY { continue }
+            if value == PRESS {
+                self.pushed.insert(code, time);
+                continue;
+            }
+            if value != RELEASE { continue }
+            // the press is complete on release
+            let pushed = match self.pushed.remove(&code) {
+                Some(t) => t,
+                None => continue,
+            };
+            let button = self.keymap.get(&code).copied();
+            if button.is_none() {
+                println!("key code {} unknown on input device", code);
+            }
+            return Ok(button.map(|button| Press {
+                button,
+                gesture: Gesture::from_duration(time.saturating_sub(pushed).as_millis()),
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    // what the kernel writes, as found in a recording of /dev/input/event*
+    fn event(seconds: usize, micros: usize, kind: u16, code: u16, value: i32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&seconds.to_ne_bytes());
+        bytes.extend_from_slice(&micros.to_ne_bytes());
+        bytes.extend_from_slice(&kind.to_ne_bytes());
+        bytes.extend_from_slice(&code.to_ne_bytes());
+        bytes.extend_from_slice(&value.to_ne_bytes());
+        bytes
+    }
+
+    fn recording() -> Vec<u8> {
+        [
+            // short press on KEY_LEFT with a sync event
+            event(10, 0, EV_KEY, 105, PRESS),
+            event(10, 0, 0, 0, 0),
+            event(10, 200_000, EV_KEY, 105, RELEASE),
+            // long press on KEY_SPACE with autorepeat
+            event(11, 0, EV_KEY, 57, PRESS),
+            event(11, 500_000, EV_KEY, 57, 2),
+            event(12, 100_000, EV_KEY, 57, RELEASE),
+            // unknown key
+            event(13, 0, EV_KEY, 30, PRESS),
+            event(13, 1000, EV_KEY, 30, RELEASE),
+            // release without press
+            event(14, 0, EV_KEY, 106, RELEASE),
+        ].concat()
+    }
+
+    #[test]
+    fn recorded_events() {
+        let path = std::env::temp_dir().join("clock-evdev-test");
+        std::fs::write(&path, recording()).unwrap();
+        let mut input = Evdev::open(path.to_str().unwrap(), &HashMap::new()).unwrap();
+        assert_eq!(input.poll_press().unwrap(), Some(Press { button: Button::Left, gesture: Gesture::Short }));
+        assert_eq!(input.poll_press().unwrap(), Some(Press { button: Button::Snooze, gesture: Gesture::Long }));
+        assert_eq!(input.poll_press().unwrap(), None);
+        assert_eq!(input.poll_press().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keymap() {
+        let keymap = vec![(30, Button::B2)].into_iter().collect();
+        let recording = recording();
+        let mut input = Evdev::new(&recording[..], &keymap);
+        // the configured keymap replaces the default one
+        assert_eq!(input.poll_press().unwrap(), None);
+        assert_eq!(input.poll_press().unwrap(), None);
+        assert_eq!(input.poll_press().unwrap(), Some(Press { button: Button::B2, gesture: Gesture::Short }));
+    }
+
+    #[test]
+    fn inputs_share_a_channel() {
+        let (tx, rx) = channel();
+        let threads: Vec<_> = (0..2).map(|_| {
+            let tx = tx.clone();
+            let input: Box<dyn Input> = Box::new(Evdev::new(io::Cursor::new(recording()), &HashMap::new()));
+            std::thread::spawn(move || input_thread(tx, input))
+        }).collect();
+        drop(tx);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let presses: Vec<Press> = rx.iter().collect();
+        assert_eq!(presses.len(), 4);
+        assert_eq!(presses.iter().filter(|p| p.button == Button::Left).count(), 2);
+    }
+}
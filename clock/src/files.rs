@@ -0,0 +1,49 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/* Files kept across restarts
+ *
+ * They are written and synced then renamed, and the directory is synced too,
+ * so that a power cut leaves either the old or the new file, never a half
+ * written or an empty one.
+ */
+
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    // state.toml and state.ics must not share their temporary file
+    let mut name = path.file_name().ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?.to_owned();
+    name.push(".tmp");
+    let tmp = dir.join(name);
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces() {
+        let dir = std::env::temp_dir().join("clock-files-test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("state.toml");
+        write_atomic(&path, b"one").unwrap();
+        write_atomic(&path, b"two").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        // another file with the same stem is left alone
+        fs::write(dir.join("state.tmp"), b"other").unwrap();
+        write_atomic(&path, b"three").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("state.tmp")).unwrap(), "other");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
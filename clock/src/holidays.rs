@@ -0,0 +1,192 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::Deserialize;
+
+use crate::config::DaysOffConfig;
+
+/* Days without alarm: public holidays and vacations
+ *
+ * Holidays are computed here, no network: fixed dates plus days relative to Easter.
+ * A forced day is never off, to get up on a holiday anyway.
+ */
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Country {
+    None,
+    #[default]
+    France,
+    Belgium,
+    Germany,
+}
+
+enum Holiday {
+    // month, day
+    Fixed(u32, u32, &'static str),
+    // days after Easter Sunday
+    Easter(i64, &'static str),
+}
+
+use Holiday::*;
+
+const FRANCE: &[Holiday] = &[
+    Fixed(1, 1, "New Year's Day"),
+    Easter(1, "Easter Monday"),
+    Fixed(5, 1, "Labour Day"),
+    Fixed(5, 8, "Victory in Europe Day"),
+    Easter(39, "Ascension"),
+    Easter(50, "Whit Monday"),
+    Fixed(7, 14, "Bastille Day"),
+    Fixed(8, 15, "Assumption"),
+    Fixed(11, 1, "All Saints' Day"),
+    Fixed(11, 11, "Armistice Day"),
+    Fixed(12, 25, "Christmas"),
+];
+
+const BELGIUM: &[Holiday] = &[
+    Fixed(1, 1, "New Year's Day"),
+    Easter(1, "Easter Monday"),
+    Fixed(5, 1, "Labour Day"),
+    Easter(39, "Ascension"),
+    Easter(50, "Whit Monday"),
+    Fixed(7, 21, "National Day"),
+    Fixed(8, 15, "Assumption"),
+    Fixed(11, 1, "All Saints' Day"),
+    Fixed(11, 11, "Armistice Day"),
+    Fixed(12, 25, "Christmas"),
+];
+
+// nationwide holidays only
+const GERMANY: &[Holiday] = &[
+    Fixed(1, 1, "New Year's Day"),
+    Easter(-2, "Good Friday"),
+    Easter(1, "Easter Monday"),
+    Fixed(5, 1, "Labour Day"),
+    Easter(39, "Ascension"),
+    Easter(50, "Whit Monday"),
+    Fixed(10, 3, "German Unity Day"),
+    Fixed(12, 25, "Christmas"),
+    Fixed(12, 26, "Second Day of Christmas"),
+];
+
+fn table(country: Country) -> &'static [Holiday] {
+    match country {
+        Country::None => &[],
+        Country::France => FRANCE,
+        Country::Belgium => BELGIUM,
+        Country::Germany => GERMANY,
+    }
+}
+
+// Easter Sunday of the gregorian calendar (anonymous gregorian algorithm)
+pub fn easter(year: i32) -> Option<NaiveDate> {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32)
+}
+
+// public holidays of a year, in the order of the table
+pub fn holidays(country: Country, year: i32) -> Vec<(NaiveDate, &'static str)> {
+    let easter = easter(year);
+    table(country).iter().filter_map(|holiday| match *holiday {
+        Fixed(month, day, name) => NaiveDate::from_ymd_opt(year, month, day).map(|d| (d, name)),
+        Easter(offset, name) => easter.map(|e| (e + Duration::days(offset), name)),
+    }).collect()
+}
+
+pub fn holiday(country: Country, day: NaiveDate) -> Option<&'static str> {
+    holidays(country, day.year()).into_iter()
+        .find(|(d, _)| *d == day)
+        .map(|(_, name)| name)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DaysOff {
+    country: Country,
+    // first and last day included
+    vacations: Vec<(NaiveDate, NaiveDate)>,
+    force: Vec<NaiveDate>,
+}
+
+impl DaysOff {
+    pub fn new(config: &DaysOffConfig) -> Self {
+        DaysOff {
+            country: config.country,
+            vacations: config.vacations.iter().map(|v| (v.from, v.to)).collect(),
+            force: config.force.clone(),
+        }
+    }
+
+    // why a day is off, None for a working day
+    pub fn reason(&self, day: NaiveDate) -> Option<String> {
+        if self.force.contains(&day) { return None }
+        if let Some(name) = holiday(self.country, day) {
+            return Some(format!("holiday {}", name));
+        }
+        self.vacations.iter()
+            .find(|(from, to)| *from <= day && day <= *to)
+            .map(|(from, to)| format!("vacation {} to {}", from, to))
+    }
+
+    pub fn is_off(&self, day: NaiveDate) -> bool {
+        self.reason(day).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn easter_sundays() {
+        for (y, m, d) in vec![(2024, 3, 31), (2025, 4, 20), (2026, 4, 5), (2000, 4, 23), (2019, 4, 21),
+                              (1818, 3, 22), (2038, 4, 25), (2285, 3, 22), (1943, 4, 25)] {
+            assert_eq!(easter(y), Some(date(y, m, d)));
+        }
+    }
+
+    #[test]
+    fn countries() {
+        let france = holidays(Country::France, 2026);
+        assert_eq!(france.len(), 11);
+        assert!(france.contains(&(date(2026, 4, 6), "Easter Monday")));
+        assert!(france.contains(&(date(2026, 5, 14), "Ascension")));
+        assert!(france.contains(&(date(2026, 5, 25), "Whit Monday")));
+        assert_eq!(holiday(Country::Belgium, date(2026, 7, 21)), Some("National Day"));
+        assert_eq!(holiday(Country::Germany, date(2026, 4, 3)), Some("Good Friday"));
+        assert_eq!(holiday(Country::France, date(2026, 4, 3)), None);
+        assert!(holidays(Country::None, 2026).is_empty());
+    }
+
+    #[test]
+    fn reasons() {
+        let config: DaysOffConfig = toml::from_str(r#"
+            country = "france"
+            vacations = [{ from = "2026-12-21", to = "2027-01-03" }]
+            force = ["2026-05-08"]
+        "#).unwrap();
+        let days_off = DaysOff::new(&config);
+        assert_eq!(days_off.reason(date(2026, 5, 8)), None);
+        assert_eq!(days_off.reason(date(2026, 5, 14)).unwrap(), "holiday Ascension");
+        assert_eq!(days_off.reason(date(2026, 12, 30)).unwrap(), "vacation 2026-12-21 to 2027-01-03");
+        // a holiday during the vacation is a holiday
+        assert_eq!(days_off.reason(date(2027, 1, 1)).unwrap(), "holiday New Year's Day");
+        assert!(days_off.is_off(date(2027, 1, 3)));
+        assert!(!days_off.is_off(date(2027, 1, 4)));
+    }
+}
@@ -0,0 +1,579 @@
+use chrono::*;
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+/* Minimal iCalendar (RFC 5545) reader, only what we need to know when to wake up
+ *
+ *  - VEVENT with DTSTART, DTEND or DURATION, SUMMARY, LOCATION, CATEGORIES, STATUS
+ *  - times in UTC, floating (local) or with a TZID known to the tz database, all-day dates
+ *  - RRULE with FREQ, INTERVAL, COUNT, UNTIL, BYDAY, BYMONTHDAY, BYMONTH
+ *  - EXDATE and modified occurrences (RECURRENCE-ID)
+ *
+ * VTIMEZONE definitions are ignored, TZIDs are looked up in the tz database instead.
+ */
+
+// never loop forever on a broken rule
+const MAX_DAYS: i64 = 100 * 366;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    pub location: String,
+    pub categories: Vec<String>,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub all_day: bool,
+}
+
+// a time as written in the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Time {
+    Date(NaiveDate),
+    Floating(NaiveDateTime),
+    Utc(NaiveDateTime),
+    Zoned(NaiveDateTime, Tz),
+}
+
+impl Time {
+    // date in the time zone of the event, recurrence rules work with it
+    fn date(&self) -> NaiveDate {
+        match self {
+            Time::Date(d) => *d,
+            Time::Floating(t) | Time::Utc(t) | Time::Zoned(t, _) => t.date(),
+        }
+    }
+
+    // same time of day on another date
+    fn with_date(&self, date: NaiveDate) -> Time {
+        match self {
+            Time::Date(_) => Time::Date(date),
+            Time::Floating(t) => Time::Floating(date.and_time(t.time())),
+            Time::Utc(t) => Time::Utc(date.and_time(t.time())),
+            Time::Zoned(t, tz) => Time::Zoned(date.and_time(t.time()), *tz),
+        }
+    }
+
+    // None for a time that doesn't exist (DST gap)
+    fn to_local(self) -> Option<DateTime<Local>> {
+        match self {
+            Time::Date(d) => Local.from_local_datetime(&d.and_time(NaiveTime::MIN)).earliest(),
+            Time::Floating(t) => Local.from_local_datetime(&t).earliest(),
+            Time::Utc(t) => Some(Utc.from_utc_datetime(&t).with_timezone(&Local)),
+            Time::Zoned(t, tz) => tz.from_local_datetime(&t).earliest().map(|t| t.with_timezone(&Local)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily, Weekly, Monthly, Yearly
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    frequency: Frequency,
+    interval: i64,
+    count: Option<u32>,
+    until: Option<Time>,
+    // (n, day) : nth day of the month, from the end when negative, every one when 0
+    by_day: Vec<(i64, Weekday)>,
+    by_month_day: Vec<i64>,
+    by_month: Vec<u32>,
+}
+
+// a VEVENT as read
+#[derive(Debug, Clone, Default)]
+struct RawEvent {
+    uid: String,
+    summary: String,
+    location: String,
+    categories: Vec<String>,
+    start: Option<Time>,
+    end: Option<Time>,
+    duration: Option<Duration>,
+    rule: Option<Rule>,
+    exdates: Vec<Time>,
+    recurrence_id: Option<Time>,
+    cancelled: bool,
+}
+
+struct Property {
+    name: String,
+    params: HashMap<String, String>,
+    value: String,
+}
+
+// all events occurring between from (included) and to (excluded)
+pub fn events_between(content: &str, from: DateTime<Local>, to: DateTime<Local>) -> Vec<Event> {
+    let raw_events = parse(content);
+    let mut events = Vec::new();
+    let mut overrides = Vec::new();
+    for raw in &raw_events {
+        if raw.recurrence_id.is_some() {
+            overrides.push(raw);
+        } else if !raw.cancelled {
+            events.append(&mut expand(raw, from, to));
+        }
+    }
+    // a modified occurrence replaces the original one
+    for raw in overrides {
+        let replaced = raw.recurrence_id.and_then(|t| t.to_local());
+        events.retain(|e| !(e.uid == raw.uid && Some(e.start) == replaced));
+        if !raw.cancelled {
+            events.append(&mut expand(raw, from, to));
+        }
+    }
+    events.sort_by_key(|e| e.start);
+    events
+}
+
+fn parse(content: &str) -> Vec<RawEvent> {
+    let mut events = Vec::new();
+    let mut current: Option<RawEvent> = None;
+    // properties of nested components (VALARM) must not be mixed with the event
+    let mut depth = 0;
+    for line in unfold(content) {
+        let property = match parse_property(&line) {
+            Some(p) => p,
+            None => continue,
+        };
+        match (property.name.as_str(), property.value.as_str()) {
+            ("BEGIN", "VEVENT") => { current = Some(RawEvent::default()); depth = 0; continue },
+            ("END", "VEVENT") => { events.extend(current.take()); continue },
+            ("BEGIN", _) => { depth += 1; continue },
+            ("END", _) => { depth -= 1; continue },
+            _ => {},
+        }
+        let event = match current.as_mut() {
+            Some(e) if depth == 0 => e,
+            _ => continue,
+        };
+        match property.name.as_str() {
+            "UID" => event.uid = property.value.clone(),
+            "SUMMARY" => event.summary = unescape(&property.value),
+            "LOCATION" => event.location = unescape(&property.value),
+            "CATEGORIES" => event.categories.extend(split_list(&property.value).iter().map(|c| unescape(c))),
+            "STATUS" => event.cancelled = property.value == "CANCELLED",
+            "DTSTART" => event.start = parse_time(&property.value, &property.params),
+            "DTEND" => event.end = parse_time(&property.value, &property.params),
+            "DURATION" => event.duration = parse_duration(&property.value),
+            "RRULE" => event.rule = parse_rule(&property.value),
+            "EXDATE" => event.exdates.extend(
+                property.value.split(',').filter_map(|v| parse_time(v, &property.params))),
+            "RECURRENCE-ID" => event.recurrence_id = parse_time(&property.value, &property.params),
+            _ => {},
+        }
+    }
+    events
+}
+
+// long lines are split, continuations start with a space or a tab
+fn unfold(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        lines.push(line.to_string());
+    }
+    lines
+}
+
+// NAME;PARAM=VALUE;PARAM="VALUE":VALUE
+fn parse_property(line: &str) -> Option<Property> {
+    // the value starts at the first colon outside quotes
+    let mut quoted = false;
+    let mut colon = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => { colon = Some(i); break },
+            _ => {},
+        }
+    }
+    let colon = colon?;
+    let mut parts = line[..colon].split(';');
+    let name = parts.next()?.to_uppercase();
+    let params = parts.filter_map(|p| {
+        let (key, value) = p.split_once('=')?;
+        Some((key.to_uppercase(), value.trim_matches('"').to_string()))
+    }).collect();
+    Some(Property { name, params, value: line[colon + 1..].to_string() })
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' { result.push(c); continue }
+        match chars.next() {
+            Some('n') | Some('N') => result.push('\n'),
+            Some(c) => result.push(c),
+            None => {},
+        }
+    }
+    result
+}
+
+// comma separated, escaped commas are part of a value
+fn split_list(value: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in value.chars() {
+        if c == ',' && !escaped {
+            result.push(current.trim().to_string());
+            current = String::new();
+            continue;
+        }
+        escaped = c == '\\' && !escaped;
+        current.push(c);
+    }
+    result.push(current.trim().to_string());
+    result
+}
+
+fn parse_time(value: &str, params: &HashMap<String, String>) -> Option<Time> {
+    let value = value.trim();
+    if params.get("VALUE").map(|v| v.as_str()) == Some("DATE") || value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(Time::Date);
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok().map(Time::Utc);
+    }
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    match params.get("TZID") {
+        Some(tzid) => match parse_tz(tzid) {
+            Some(tz) => Some(Time::Zoned(time, tz)),
+            None => {
+                println!("Unknown time zone {}, using local time", tzid);
+                Some(Time::Floating(time))
+            },
+        },
+        None => Some(Time::Floating(time)),
+    }
+}
+
+// some software prefixes the tz database name with a path
+fn parse_tz(tzid: &str) -> Option<Tz> {
+    if let Ok(tz) = tzid.parse() { return Some(tz) }
+    tzid.match_indices('/').find_map(|(i, _)| tzid[i + 1..].parse().ok())
+}
+
+// P1D, PT1H30M, -PT15M, P2W
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(v) => (true, v),
+        None => (false, value.trim_start_matches('+')),
+    };
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in value.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => { number.push(c); continue },
+            'T' => continue,
+            'W' => seconds += number.parse::<i64>().ok()? * 7 * 86400,
+            'D' => seconds += number.parse::<i64>().ok()? * 86400,
+            'H' => seconds += number.parse::<i64>().ok()? * 3600,
+            'M' => seconds += number.parse::<i64>().ok()? * 60,
+            'S' => seconds += number.parse::<i64>().ok()?,
+            _ => return None,
+        }
+        number.clear();
+    }
+    let duration = Duration::seconds(seconds);
+    Some(if negative { -duration } else { duration })
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    Some(match value {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+// FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;UNTIL=20261231T000000Z
+fn parse_rule(value: &str) -> Option<Rule> {
+    let mut rule = Rule {
+        frequency: Frequency::Daily,
+        interval: 1,
+        count: None,
+        until: None,
+        by_day: Vec::new(),
+        by_month_day: Vec::new(),
+        by_month: Vec::new(),
+    };
+    let mut frequency = None;
+    for part in value.split(';') {
+        let (key, value) = match part.split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        match key {
+            "FREQ" => frequency = match value {
+                "DAILY" => Some(Frequency::Daily),
+                "WEEKLY" => Some(Frequency::Weekly),
+                "MONTHLY" => Some(Frequency::Monthly),
+                "YEARLY" => Some(Frequency::Yearly),
+                _ => { println!("Unsupported recurrence {}", value); return None },
+            },
+            "INTERVAL" => rule.interval = value.parse().ok().filter(|i| *i > 0)?,
+            "COUNT" => rule.count = value.parse().ok(),
+            "UNTIL" => rule.until = parse_time(value, &HashMap::new()),
+            "BYDAY" => for day in value.split(',') {
+                let split = day.len().checked_sub(2)?;
+                let n = if split == 0 { 0 } else { day[..split].parse().ok()? };
+                rule.by_day.push((n, parse_weekday(&day[split..])?));
+            },
+            "BYMONTHDAY" => rule.by_month_day = value.split(',').filter_map(|d| d.parse().ok()).collect(),
+            "BYMONTH" => rule.by_month = value.split(',').filter_map(|m| m.parse().ok()).collect(),
+            _ => {},
+        }
+    }
+    rule.frequency = frequency?;
+    Some(rule)
+}
+
+fn days_in_month(date: NaiveDate) -> i64 {
+    let first = date.with_day(1).unwrap();
+    let next = first.checked_add_months(Months::new(1)).unwrap();
+    (next - first).num_days()
+}
+
+fn month_index(date: NaiveDate) -> i64 {
+    date.year() as i64 * 12 + date.month0() as i64
+}
+
+impl Rule {
+    // does the rule produce this date, start being the date of the first occurrence
+    fn matches(&self, start: NaiveDate, date: NaiveDate) -> bool {
+        if date < start { return false }
+        if !self.by_month.is_empty() && !self.by_month.contains(&date.month()) { return false }
+        match self.frequency {
+            Frequency::Daily =>
+                (date - start).num_days() % self.interval == 0
+                    && (self.by_day.is_empty() || self.by_day.iter().any(|(_, d)| *d == date.weekday())),
+            Frequency::Weekly => {
+                let weeks = (date.week(Weekday::Mon).first_day() - start.week(Weekday::Mon).first_day()).num_days() / 7;
+                let day_matches = if self.by_day.is_empty() {
+                    date.weekday() == start.weekday()
+                } else {
+                    self.by_day.iter().any(|(_, d)| *d == date.weekday())
+                };
+                weeks % self.interval == 0 && day_matches
+            },
+            Frequency::Monthly =>
+                (month_index(date) - month_index(start)) % self.interval == 0 && self.day_matches(start, date),
+            Frequency::Yearly => {
+                let month_matches = !self.by_month.is_empty() || date.month() == start.month();
+                (date.year() - start.year()) as i64 % self.interval == 0 && month_matches
+                    && self.day_matches(start, date)
+            },
+        }
+    }
+
+    // day in the month for monthly and yearly rules
+    fn day_matches(&self, start: NaiveDate, date: NaiveDate) -> bool {
+        let day = date.day() as i64;
+        let last = days_in_month(date);
+        if !self.by_month_day.is_empty() {
+            return self.by_month_day.iter().any(|d| *d == day || (*d < 0 && last + d + 1 == day));
+        }
+        if !self.by_day.is_empty() {
+            return self.by_day.iter().any(|(n, weekday)| {
+                *weekday == date.weekday() && match n {
+                    0 => true,
+                    n if *n > 0 => (day - 1) / 7 + 1 == *n,
+                    n => (last - day) / 7 + 1 == -n,
+                }
+            });
+        }
+        date.day() == start.day()
+    }
+}
+
+// occurrences of an event overlapping [from, to[
+fn expand(raw: &RawEvent, from: DateTime<Local>, to: DateTime<Local>) -> Vec<Event> {
+    let start = match raw.start {
+        Some(s) => s,
+        None => return Vec::new(),
+    };
+    let all_day = matches!(start, Time::Date(_));
+    let first = match start.to_local() {
+        Some(t) => t,
+        None => return Vec::new(),
+    };
+    let duration = match (raw.end.and_then(|e| e.to_local()), raw.duration) {
+        (Some(end), _) => end - first,
+        (None, Some(duration)) => duration,
+        (None, None) if all_day => Duration::days(1),
+        (None, None) => Duration::zero(),
+    };
+    let excluded: Vec<DateTime<Local>> = raw.exdates.iter().filter_map(|t| t.to_local()).collect();
+    let occurrence = |time: Time| -> Option<Event> {
+        let start = time.to_local()?;
+        if excluded.contains(&start) { return None }
+        let end = start + duration;
+        // zero length events still count when they start in the range
+        if start >= to || end < from || (end == from && duration > Duration::zero()) { return None }
+        Some(Event {
+            uid: raw.uid.clone(),
+            summary: raw.summary.clone(),
+            location: raw.location.clone(),
+            categories: raw.categories.clone(),
+            start,
+            end,
+            all_day,
+        })
+    };
+    let rule = match raw.rule {
+        Some(ref r) if raw.recurrence_id.is_none() => r,
+        _ => return occurrence(start).into_iter().collect(),
+    };
+    // with a count, occurrences must be counted from the start
+    let start_date = start.date();
+    let mut date = if rule.count.is_some() {
+        start_date
+    } else {
+        // time zones can move dates by a day
+        start_date.max((from - duration).date_naive() - Duration::days(2))
+    };
+    let mut count = 0;
+    let mut events = Vec::new();
+    for _ in 0..MAX_DAYS {
+        if date > to.date_naive() + Duration::days(2) { break }
+        if rule.count.is_some_and(|c| count >= c) { break }
+        if rule.matches(start_date, date) {
+            let time = start.with_date(date);
+            let over = match rule.until {
+                Some(Time::Date(until)) => date > until,
+                Some(until) => time.to_local().zip(until.to_local()).is_some_and(|(t, u)| t > u),
+                None => false,
+            };
+            if over { break }
+            count += 1;
+            events.extend(occurrence(time));
+        }
+        date = match date.succ_opt() {
+            Some(d) => d,
+            None => break,
+        };
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Paris;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+BEGIN:VTIMEZONE\r
+TZID:Europe/Paris\r
+BEGIN:STANDARD\r
+DTSTART:19701025T030000\r
+END:STANDARD\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:weekly\r
+SUMMARY:Weekly\\, meeting\r
+DTSTART;TZID=Europe/Paris:20260105T090000\r
+DTEND;TZID=Europe/Paris:20260105T100000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE;UNTIL=20261231T235959Z\r
+EXDATE;TZID=Europe/Paris:20261104T090000\r
+LOCATION:Office\r
+CATEGORIES:work,no-wake\r
+BEGIN:VALARM\r
+TRIGGER:-PT15M\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:weekly\r
+RECURRENCE-ID;TZID=Europe/Paris:20261102T090000\r
+DTSTART;TZID=Europe/Paris:20261102T110000\r
+DURATION:PT1H\r
+SUMMARY:moved\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:day\r
+SUMMARY:holiday\r
+DTSTART;VALUE=DATE:20261103\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:monthly\r
+SUMMARY:last friday\r
+DTSTART:20260130T070000Z\r
+RRULE:FREQ=MONTHLY;BYDAY=-1FR;COUNT=20\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:floating\r
+SUMMARY:long\r
+  folded\r
+DTSTART:20261102T080000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    fn paris(y: i32, m: u32, d: u32, h: u32) -> DateTime<Local> {
+        Paris.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap().with_timezone(&Local)
+    }
+
+    fn summaries(events: &[Event]) -> Vec<(&str, DateTime<Local>, DateTime<Local>)> {
+        let mut summaries: Vec<_> = events.iter().map(|e| (e.summary.as_str(), e.start, e.end)).collect();
+        // by name, the order of times depends on the local time zone
+        summaries.sort();
+        summaries
+    }
+
+    // moved, excluded, all day and floating events
+    #[test]
+    fn exceptions() {
+        let events = events_between(CALENDAR, local(2026, 11, 1, 0, 0), local(2026, 11, 8, 0, 0));
+        assert_eq!(summaries(&events), vec![
+            ("holiday", local(2026, 11, 3, 0, 0), local(2026, 11, 4, 0, 0)),
+            ("long folded", local(2026, 11, 2, 8, 0), local(2026, 11, 2, 8, 0)),
+            ("moved", paris(2026, 11, 2, 11), paris(2026, 11, 2, 12)),
+        ]);
+        assert!(events.iter().find(|e| e.summary == "holiday").unwrap().all_day);
+    }
+
+    #[test]
+    fn weekly() {
+        let events = events_between(CALENDAR, local(2026, 11, 8, 0, 0), local(2026, 11, 15, 0, 0));
+        assert_eq!(summaries(&events), vec![
+            ("Weekly, meeting", paris(2026, 11, 9, 9), paris(2026, 11, 9, 10)),
+            ("Weekly, meeting", paris(2026, 11, 11, 9), paris(2026, 11, 11, 10)),
+        ]);
+        assert_eq!(events[0].location, "Office");
+        assert_eq!(events[0].categories, vec!["work", "no-wake"]);
+        // until the end of the year
+        assert!(events_between(CALENDAR, local(2027, 1, 1, 0, 0), local(2027, 1, 15, 0, 0)).iter()
+            .all(|e| e.uid != "weekly"));
+    }
+
+    // the last occurrence is in august
+    #[test]
+    fn monthly() {
+        let events = events_between(CALENDAR, local(2027, 7, 1, 0, 0), local(2027, 10, 1, 0, 0));
+        let utc = |m, d| Utc.with_ymd_and_hms(2027, m, d, 7, 0, 0).unwrap().with_timezone(&Local);
+        assert_eq!(summaries(&events), vec![
+            ("last friday", utc(7, 30), utc(7, 30)),
+            ("last friday", utc(8, 27), utc(8, 27)),
+        ]);
+    }
+}
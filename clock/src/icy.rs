@@ -0,0 +1,135 @@
+use std::io::{ErrorKind, Read, Result};
+
+/* Shoutcast/icecast metadata
+ *
+ * Asked with an Icy-MetaData header, the server inserts a metadata block
+ * every icy-metaint bytes of audio: one byte giving the length in 16 bytes
+ * units, then text like StreamTitle='Artist - Song';StreamUrl='';
+ * The reader gives the audio only and tells the titles as they come.
+ */
+
+pub struct IcyReader<R> {
+    inner: R,
+    metaint: usize,
+    // audio bytes before the next metadata block
+    left: usize,
+    title: Option<String>,
+    on_title: Box<dyn FnMut(Option<String>) + Send>,
+}
+
+impl<R: Read> IcyReader<R> {
+    pub fn new(inner: R, metaint: usize, on_title: impl FnMut(Option<String>) + Send + 'static) -> Self {
+        IcyReader { inner, metaint, left: metaint, title: None, on_title: Box::new(on_title) }
+    }
+
+    // false at the end of the stream
+    fn read_metadata(&mut self) -> Result<bool> {
+        let mut length = [0u8; 1];
+        if self.inner.read(&mut length)? == 0 { return Ok(false) }
+        let mut block = vec![0; length[0] as usize * 16];
+        match self.inner.read_exact(&mut block) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        // an empty block means nothing changed
+        if block.is_empty() { return Ok(true) }
+        let title = stream_title(&block);
+        if title != self.title {
+            self.title = title.clone();
+            (self.on_title)(title);
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for IcyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.metaint == 0 {
+            return self.inner.read(buf);
+        }
+        if self.left == 0 {
+            if !self.read_metadata()? { return Ok(0) }
+            self.left = self.metaint;
+        }
+        let length = buf.len().min(self.left);
+        let n = self.inner.read(&mut buf[..length])?;
+        self.left -= n;
+        Ok(n)
+    }
+}
+
+// the title may hold quotes, it ends with ';
+pub fn stream_title(block: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(block);
+    let text = text.trim_end_matches('\0');
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &text[start..];
+    let title = match rest.find("';") {
+        Some(end) => &rest[..end],
+        None => rest.trim_end_matches('\''),
+    };
+    let title = title.trim();
+    if title.is_empty() { None } else { Some(title.to_string()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    // metadata padded to 16 bytes units
+    fn block(text: &str) -> Vec<u8> {
+        let mut block = text.as_bytes().to_vec();
+        block.resize(text.len().div_ceil(16) * 16, 0);
+        let mut with_length = vec![(block.len() / 16) as u8];
+        with_length.extend(block);
+        with_length
+    }
+
+    #[test]
+    fn titles() {
+        assert_eq!(stream_title(b"StreamTitle='Artist - Song';StreamUrl='';\0\0"), Some("Artist - Song".to_string()));
+        assert_eq!(stream_title(b"StreamTitle='Rock'n'roll';"), Some("Rock'n'roll".to_string()));
+        assert_eq!(stream_title(b"StreamTitle='Cut"), Some("Cut".to_string()));
+        assert_eq!(stream_title(b"StreamTitle=' ';"), None);
+        assert_eq!(stream_title(b"StreamUrl='';"), None);
+    }
+
+    #[test]
+    fn reader() {
+        let mut stream = b"abcd".to_vec();
+        stream.extend(block("StreamTitle='One';"));
+        stream.extend(b"efgh");
+        // nothing changed
+        stream.push(0);
+        stream.extend(b"ijkl");
+        stream.extend(block("StreamTitle='One';"));
+        stream.extend(b"mnop");
+        stream.extend(block("StreamTitle='';"));
+        stream.extend(b"qr");
+        let titles = Arc::new(Mutex::new(Vec::new()));
+        let heard = titles.clone();
+        let mut reader = IcyReader::new(Cursor::new(stream), 4, move |t| heard.lock().unwrap().push(t));
+        let mut audio = String::new();
+        reader.read_to_string(&mut audio).unwrap();
+        assert_eq!(audio, "abcdefghijklmnopqr");
+        assert_eq!(*titles.lock().unwrap(), [Some("One".to_string()), None]);
+    }
+
+    #[test]
+    fn cut_metadata() {
+        let mut stream = b"abcd".to_vec();
+        stream.extend(&block("StreamTitle='One';")[..5]);
+        let mut reader = IcyReader::new(Cursor::new(stream), 4, |_| ());
+        let mut audio = Vec::new();
+        reader.read_to_end(&mut audio).unwrap();
+        assert_eq!(audio, b"abcd");
+        // without metadata
+        let mut reader = IcyReader::new(Cursor::new(b"abcd".to_vec()), 0, |_| ());
+        audio.clear();
+        reader.read_to_end(&mut audio).unwrap();
+        assert_eq!(audio, b"abcd");
+    }
+}
@@ -0,0 +1,136 @@
+use rppal::gpio::Gpio;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::buttons::*;
+use crate::config::InputConfig;
+use crate::evdev::*;
+use crate::keys::*;
+use crate::rotary::*;
+
+// a push shorter than this is a short press
+const LONG_PRESS_MS: u128 = 700;
+// wait after an input error, doubled each time until the input works again
+const ERROR_DELAY: Duration = Duration::from_millis(100);
+const MAX_ERROR_DELAY: Duration = Duration::from_secs(5);
+// errno of a device that has been unplugged
+const ENODEV: i32 = 19;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Gesture {
+    Short, Long
+}
+
+impl Gesture {
+    pub fn from_duration(ms: u128) -> Self {
+        if ms < LONG_PRESS_MS { Gesture::Short } else { Gesture::Long }
+    }
+}
+
+// a button event as sent to the main thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Press {
+    pub button: Button,
+    pub gesture: Gesture,
+}
+
+/* Anything that can produce button events
+ *
+ * Each input runs in its own thread and blocks in poll_button,
+ * all of them send to the same channel so the main thread doesn't care where a button comes from.
+ */
+pub trait Input: Send {
+    // wait for the next button press, None when woken up for nothing
+    // an UnexpectedEof error means this input will never produce anything again
+    fn poll_press(&mut self) -> io::Result<Option<Press>>;
+}
+
+impl Input for Keys {
+    fn poll_press(&mut self) -> io::Result<Option<Press>> {
+        Keys::poll_button(self).map_err(io::Error::other)
+    }
+}
+
+pub fn open_input(config: &InputConfig, gpio: &Arc<Gpio>) -> io::Result<Box<dyn Input>> {
+    Ok(match config {
+        InputConfig::Keys =>
+            Box::new(Keys::new(gpio.clone()).map_err(io::Error::other)?),
+        InputConfig::Evdev { path, keymap } => {
+            // toml keys are always strings
+            let mut codes = HashMap::new();
+            for (code, button) in keymap {
+                match code.parse::<u16>() {
+                    Ok(c) => { codes.insert(c, *button); },
+                    Err(_) => println!("Invalid key code {} for {}", code, path),
+                }
+            }
+            Box::new(Evdev::open(path, &codes)?)
+        },
+        InputConfig::Gpio { buttons, debounce_ms } => {
+            let buttons: Vec<(u8, Button)> = buttons.iter().map(|b| (b.pin, b.button)).collect();
+            Box::new(GpioButtons::new(gpio, &buttons, Duration::from_millis(*debounce_ms)).map_err(io::Error::other)?)
+        },
+        InputConfig::Rotary { pin_a, pin_b, clockwise, counter_clockwise, steps } =>
+            Box::new(Rotary::new(gpio, *pin_a, *pin_b, *clockwise, *counter_clockwise, *steps).map_err(io::Error::other)?),
+    })
+}
+
+pub fn input_thread(tx: Sender<Press>, mut input: Box<dyn Input>) {
+    let mut delay = ERROR_DELAY;
+    loop {
+        match input.poll_press() {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                println!("Input closed");
+                return;
+            },
+            Err(e) if e.raw_os_error() == Some(ENODEV) => {
+                println!("Input device gone");
+                return;
+            },
+            // don't spin on a failing input
+            Err(e) => {
+                println!("Error {:?}", e);
+                sleep(delay);
+                delay = (delay * 2).min(MAX_ERROR_DELAY);
+                continue;
+            },
+            Ok(None) => {},
+            // send key to master thread via mspc
+            Ok(Some(press)) => tx.send(press).unwrap(),
+        }
+        delay = ERROR_DELAY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    struct Failing(Vec<io::Result<Option<Press>>>);
+
+    impl Input for Failing {
+        fn poll_press(&mut self) -> io::Result<Option<Press>> {
+            self.0.remove(0)
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let press = Press { button: Button::Snooze, gesture: Gesture::Short };
+        let input = Failing(vec![
+            Err(io::Error::other("glitch")),
+            Ok(Some(press)),
+            Err(io::Error::from_raw_os_error(ENODEV)),
+        ]);
+        let (tx, rx) = channel();
+        // returns instead of panicking on the empty list
+        input_thread(tx, Box::new(input));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![press]);
+    }
+}
@@ -0,0 +1,112 @@
+use chrono::Local;
+use rppal::gpio::*;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::*;
+
+use crate::keys::*;
+
+/* Key diagnostics : measure both key lines continuously and show what we get
+ *
+ *  - one histogram of charge times per line
+ *  - classification windows are shown next to the histogram
+ *  - unrecognised readings are logged with their timestamp
+ *
+ * Run with `clock keys`, keep a button pushed to see its charge time distribution.
+ */
+
+// histogram buckets are logarithmic to keep precision on small resistors
+const BUCKETS: usize = 32;
+const MIN_US: f64 = 5.;
+const BAR_WIDTH: u64 = 40;
+const SAMPLE_MS: u64 = 5;
+const REFRESH_MS: u128 = 500;
+const LOG_SIZE: usize = 10;
+
+struct Histogram {
+    counts: [u64; BUCKETS],
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram { counts: [0; BUCKETS] }
+    }
+
+    // lower bound of a bucket in us
+    fn bound(bucket: usize) -> u128 {
+        let ratio = (MAX_CHARGE_US as f64 / MIN_US).powf(bucket as f64 / BUCKETS as f64);
+        (MIN_US * ratio) as u128
+    }
+
+    fn bucket(value: u128) -> usize {
+        (1..BUCKETS).rev()
+            .find(|b| value >= Histogram::bound(*b))
+            .unwrap_or(0)
+    }
+
+    fn add(&mut self, value: u128) {
+        self.counts[Histogram::bucket(value)] += 1;
+    }
+
+    fn print(&self, keys: usize) {
+        let max = *self.counts.iter().max().unwrap_or(&0);
+        let total: u64 = self.counts.iter().sum();
+        println!("Line {} ({} readings)", keys, total);
+        for b in 0..BUCKETS {
+            let low = Histogram::bound(b);
+            let high = if b + 1 < BUCKETS { Histogram::bound(b + 1) } else { MAX_CHARGE_US };
+            let bar = (self.counts[b] * BAR_WIDTH).checked_div(max).unwrap_or(0);
+            println!("{:>5}-{:<5} {:>6} {:<width$} {}",
+                     low, high, self.counts[b], "#".repeat(bar as usize), windows_label(keys, low, high),
+                     width = BAR_WIDTH as usize);
+        }
+    }
+}
+
+// buttons whose classification window overlaps [low, high[
+fn windows_label(keys: usize, low: u128, high: u128) -> String {
+    WINDOWS[keys].iter()
+        .filter(|(expected, _)| {
+            let (wlow, whigh) = range(*expected);
+            wlow < high && whigh > low
+        })
+        .map(|(_, button)| format!("[{:?}]", button))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn keys_diagnostics(gpio: Arc<Gpio>) -> Result<()> {
+    let mut keys = Keys::new(gpio)?;
+    let mut histograms = [Histogram::new(), Histogram::new()];
+    let mut log: Vec<String> = Vec::new();
+    let mut last_print = Instant::now();
+    loop {
+        for (line, histogram) in histograms.iter_mut().enumerate() {
+            // None means no button pushed on this line
+            if let Some(resistor) = keys.sample(line)? {
+                histogram.add(resistor);
+                if classify(line, resistor).is_none() {
+                    let entry = format!("{} line {} unknown charge time {}us",
+                                        Local::now().format("%H:%M:%S%.3f"), line, resistor);
+                    eprintln!("{}", entry);
+                    log.push(entry);
+                    if log.len() > LOG_SIZE { log.remove(0); }
+                }
+            }
+        }
+        if last_print.elapsed().as_millis() > REFRESH_MS {
+            // clear screen and go home
+            print!("\x1b[2J\x1b[H");
+            histograms[0].print(0);
+            println!();
+            histograms[1].print(1);
+            println!();
+            println!("Unrecognised readings:");
+            for entry in &log {
+                println!("{}", entry);
+            }
+            last_print = Instant::now();
+        }
+        sleep(Duration::from_millis(SAMPLE_MS));
+    }
+}
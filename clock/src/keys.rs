@@ -1,8 +1,11 @@
 use rppal::gpio::*;
+use serde::Deserialize;
 use std::time::*;
 use std::thread::sleep;
 use std::sync::Arc;
 
+use crate::input::{Gesture, Press};
+
 /* Keys are all connected to the same pin and just have a different resistance value
  *
  *
@@ -18,10 +21,10 @@ use std::sync::Arc;
 
 const KEY_PIN: [u8; 2] = [27, 22]; // 0 -> KEY0, 1 -> KEY1
 const MAX_PUSH_MS: u64 = 1000;
-const MAX_CHARGE_US: u128 = 6000;
+pub const MAX_CHARGE_US: u128 = 6000;
 const DISCHARGE_MS: u64 = 10;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 pub enum Button {
     Snooze, B1, B2, Time, SpkrLow, SpkrHigh, Left, Right, OnOff
 }
@@ -39,25 +42,25 @@ impl Keys {
     pub fn new(gpio: Arc<Gpio>) -> Result<Self> {
         let mut keys = Keys { gpio };
         keys.discharge()?;
-        return Ok(keys);
+        Ok(keys)
     }
 
     fn get_input_nopull(&self, keys: usize) -> Result<InputPin> {
         let mut pin = self.gpio.get(KEY_PIN[keys])?.into_input();
         pin.set_reset_on_drop(false);
-        return Ok(pin);
+        Ok(pin)
     }
 
     fn get_input_pulldown(&self, keys: usize) -> Result<InputPin> {
         let mut pin = self.gpio.get(KEY_PIN[keys])?.into_input_pulldown();
         pin.set_reset_on_drop(false);
-        return Ok(pin);
+        Ok(pin)
     }
 
     fn get_output(&self, keys: usize) -> Result<OutputPin> {
         let mut pin = self.gpio.get(KEY_PIN[keys])?.into_output();
         pin.set_reset_on_drop(false);
-        return Ok(pin);
+        Ok(pin)
     }
 
     fn discharge(&mut self) -> Result<()> {
@@ -118,37 +121,35 @@ impl Keys {
         let duration = start.elapsed().as_millis();
         self.discharge()?;
         
-        return Ok(Some((resistor, duration)));
+        Ok(Some((resistor, duration)))
     }
 
-    #[rustfmt::skip]
-    fn detect_button(&mut self, keys: usize) -> Result<Option<Button>> {
+    fn detect_button(&mut self, keys: usize) -> Result<Option<Press>> {
         let (resistor, time) = match self.measure_push(keys)? {
             Some(x) => x,
             None => return Ok(None),
         };
-        Ok(if keys == 0 {
-                 if in_range(resistor,1700) { Some(Button::Right) } 
-            else if in_range(resistor, 850) { Some(Button::Left) }
-            else if in_range(resistor, 200) { Some(Button::SpkrHigh) }
-            else if in_range(resistor,  15) { Some(Button::SpkrLow) }
-            else { println!("resistor {} unknown on line 0", resistor); None}
-        } else {
-                 if in_range(resistor, 4300) { Some(Button::B2) }
-            else if in_range(resistor, 1900) { Some(Button::B1) }
-            else if in_range(resistor,  950) { Some(Button::Snooze) }
-            else if in_range(resistor,  250) { Some(Button::Time) }
-            else if in_range(resistor,   15) { Some(Button::OnOff) }
-            else { println!("resistor {} unknown on line 1", resistor); None }
-        })
+        let button = classify(keys, resistor);
+        if button.is_none() {
+            println!("resistor {} unknown on line {}", resistor, keys);
+        }
+        Ok(button.map(|button| Press { button, gesture: Gesture::from_duration(time) }))
+    }
+
+    // raw charge time of a line, as used by button detection (for diagnostics)
+    pub fn sample(&mut self, keys: usize) -> Result<Option<u128>> {
+        self.discharge()?;
+        let resistor = self.measure_resistor(keys)?;
+        self.discharge()?;
+        Ok(resistor)
     }
 
-    pub fn poll_button(&mut self) -> Result<Option<Button>> {
+    pub fn poll_button(&mut self) -> Result<Option<Press>> {
         let keys = {
             let mut pin0 = self.get_input_pulldown(0)?;
             let mut pin1 = self.get_input_pulldown(1)?;
-            pin0.set_interrupt(Trigger::RisingEdge)?;
-            pin1.set_interrupt(Trigger::RisingEdge)?;
+            pin0.set_interrupt(Trigger::RisingEdge, None)?;
+            pin1.set_interrupt(Trigger::RisingEdge, None)?;
             let pins = [&pin0, &pin1];
             let res = self.gpio.poll_interrupts(&pins, true, None)?;
             match res {
@@ -160,10 +161,41 @@ impl Keys {
     }
 }
 
-// allow variation on resistor measurement
+// expected charge time (us) for each button of a line
+#[rustfmt::skip]
+pub const WINDOWS: [&[(u128, Button)]; 2] = [
+    &[
+        (1700, Button::Right),
+        ( 850, Button::Left),
+        ( 200, Button::SpkrHigh),
+        (  15, Button::SpkrLow),
+    ],
+    &[
+        (4300, Button::B2),
+        (1900, Button::B1),
+        ( 950, Button::Snooze),
+        ( 250, Button::Time),
+        (  15, Button::OnOff),
+    ],
+];
+
+// first button of the line whose window contains the measure
+pub fn classify(keys: usize, resistor: u128) -> Option<Button> {
+    WINDOWS[keys].iter()
+        .find(|(expected, _)| in_range(resistor, *expected))
+        .map(|(_, button)| *button)
+}
+
+// allow variation on resistor measurement, bounds are exclusive
+pub fn range(expected: u128) -> (u128, u128) {
+    if expected <= 30 { return (0, expected * 2) }
+    (expected * 7 / 10, expected * 14 / 10)
+}
+
 fn in_range(value: u128, expected: u128) -> bool {
-    if expected <= 30 { return value < (expected * 2) }
-    return value > (expected * 7 / 10 ) && value < (expected * 14 / 10)
+    let (low, high) = range(expected);
+    if low == 0 { return value < high }
+    value > low && value < high
 }
 
 // average with Option integers
@@ -0,0 +1,45 @@
+use chrono::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::files::write_atomic;
+
+/* Last time the clock was running, to find the alarms missed while it was off
+ *
+ * Saved every minute in the state directory, as an rfc 3339 date.
+ */
+
+const FILE: &str = "last_seen";
+
+// None the first time or when the file is broken
+pub fn load_last_seen(state_dir: &str) -> Option<DateTime<Local>> {
+    let content = fs::read_to_string(Path::new(state_dir).join(FILE)).ok()?;
+    match DateTime::parse_from_rfc3339(content.trim()) {
+        Ok(time) => Some(time.with_timezone(&Local)),
+        Err(e) => { println!("Invalid last seen time {}: {}", content.trim(), e); None },
+    }
+}
+
+pub fn save_last_seen(state_dir: &str, time: DateTime<Local>) -> io::Result<()> {
+    write_atomic(&Path::new(state_dir).join(FILE), time.to_rfc3339().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved() {
+        let dir = std::env::temp_dir().join("clock-last-seen-test");
+        let dir = dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(dir);
+        assert_eq!(load_last_seen(dir), None);
+        let time = Local.with_ymd_and_hms(2026, 10, 20, 7, 30, 0).unwrap();
+        save_last_seen(dir, time).unwrap();
+        assert_eq!(load_last_seen(dir), Some(time));
+        fs::write(Path::new(dir).join(FILE), "yesterday").unwrap();
+        assert_eq!(load_last_seen(dir), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
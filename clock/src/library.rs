@@ -0,0 +1,200 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::files::write_atomic;
+
+/* Local music played like a station
+ *
+ * A station whose url is a directory plays the audio files found in it and
+ * its subdirectories, shuffled. Tracks played recently go last, so the
+ * last track heard is not the first one of the next alarm. What was played
+ * is saved in the state directory.
+ */
+
+const FILE: &str = "history";
+// tracks remembered
+const HISTORY_LENGTH: usize = 200;
+// tracks given to the player at once, it loops on them
+const PLAYLIST_LENGTH: usize = 50;
+const EXTENSIONS: &[&str] = &["mp3", "ogg", "oga", "opus", "flac", "aac", "m4a", "wav"];
+
+pub struct Library {
+    state_dir: String,
+    // oldest first
+    history: Vec<String>,
+}
+
+impl Library {
+    // a missing history is empty
+    pub fn load(state_dir: &str) -> Self {
+        let history = match fs::read_to_string(Path::new(state_dir).join(FILE)) {
+            Ok(content) => content.lines().filter(|l| !l.is_empty()).map(|l| l.to_string()).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => { println!("Cannot read the play history {:?}", e); Vec::new() },
+        };
+        Library { state_dir: state_dir.to_string(), history }
+    }
+
+    pub fn is_library(url: &str) -> bool {
+        Path::new(url).is_dir()
+    }
+
+    // shuffled tracks of the directory, never played first then the oldest played
+    pub fn playlist(&self, dir: &str) -> Vec<String> {
+        let mut tracks = Vec::new();
+        scan(Path::new(dir), &mut tracks);
+        tracks.sort();
+        shuffle(&mut tracks, seed());
+        tracks.sort_by_key(|track| self.history.iter().rposition(|played| same_track(track, played)));
+        tracks.truncate(PLAYLIST_LENGTH);
+        tracks
+    }
+
+    // the input the player tells, saved when it changes
+    pub fn played(&mut self, input: &str) {
+        let track = track_path(input);
+        if self.history.last() == Some(&track) { return }
+        self.history.push(track);
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.drain(..self.history.len() - HISTORY_LENGTH);
+        }
+        if let Err(e) = self.save() {
+            println!("Cannot save the play history {:?}", e);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut content = self.history.join("\n");
+        content.push('\n');
+        write_atomic(&Path::new(&self.state_dir).join(FILE), content.as_bytes())
+    }
+}
+
+// audio files by their extension, unreadable directories are skipped
+fn scan(dir: &Path, tracks: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => { println!("Cannot read {} {:?}", dir.display(), e); return },
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            scan(&path, tracks);
+        } else if path.extension().and_then(|e| e.to_str())
+                .is_some_and(|e| EXTENSIONS.contains(&e.to_lowercase().as_str())) {
+            if let Some(path) = path.to_str() {
+                tracks.push(path.to_string());
+            }
+        }
+    }
+}
+
+fn seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |d| d.as_nanos() as u64) | 1
+}
+
+// fisher yates with xorshift, no need for a better random
+fn shuffle(tracks: &mut [String], mut seed: u64) {
+    for i in (1..tracks.len()).rev() {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        tracks.swap(i, (seed % (i as u64 + 1)) as usize);
+    }
+}
+
+// players tell urls, mpd tells paths in its own music directory
+fn same_track(track: &str, played: &str) -> bool {
+    track == played || (track.ends_with(played) && track[..track.len() - played.len()].ends_with('/'))
+}
+
+// file:///music/caf%C3%A9.mp3 is /music/café.mp3
+fn track_path(input: &str) -> String {
+    let path = match input.strip_prefix("file://") {
+        Some(path) => path,
+        None => return input.to_string(),
+    };
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = if bytes[i] == b'%' { path.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) } else { None };
+        match hex {
+            Some(byte) => { decoded.push(byte); i += 3 },
+            None => { decoded.push(bytes[i]); i += 1 },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("clock-library-{}-test", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn shuffled_tracks() {
+        let music = dir("music");
+        fs::create_dir_all(format!("{}/album", music)).unwrap();
+        for name in ["a.mp3", "b.OGG", "cover.jpg", "album/c.flac", "album/notes.txt"] {
+            fs::write(format!("{}/{}", music, name), "").unwrap();
+        }
+        assert!(Library::is_library(&music) && !Library::is_library(&format!("{}/a.mp3", music)));
+        let library = Library::load(&dir("shuffled"));
+        let mut tracks = library.playlist(&music);
+        tracks.sort();
+        assert_eq!(tracks, [format!("{}/a.mp3", music), format!("{}/album/c.flac", music), format!("{}/b.OGG", music)]);
+        let mut shuffled: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        shuffle(&mut shuffled, 12345);
+        assert_ne!(shuffled, (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+        shuffled.sort_by_key(|t| t.parse::<u32>().unwrap());
+        assert_eq!(shuffled, (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn played_last() {
+        let music = dir("played");
+        for name in ["a.mp3", "b.mp3", "c.mp3"] {
+            fs::write(format!("{}/{}", music, name), "").unwrap();
+        }
+        let state = dir("history");
+        let mut library = Library::load(&state);
+        // a player url, then a path in the mpd music directory
+        library.played(&format!("file://{}/b.mp3", music));
+        library.played("c.mp3");
+        library.played("c.mp3");
+        let history = fs::read_to_string(format!("{}/{}", state, FILE)).unwrap();
+        assert_eq!(history, format!("{}/b.mp3\nc.mp3\n", music));
+        // kept for the next alarms
+        let library = Library::load(&state);
+        let tracks = library.playlist(&music);
+        assert_eq!(tracks[1..], [format!("{}/b.mp3", music), format!("{}/c.mp3", music)]);
+    }
+
+    #[test]
+    fn history_length() {
+        let mut library = Library::load(&dir("length"));
+        for i in 0..HISTORY_LENGTH + 10 {
+            library.played(&i.to_string());
+        }
+        assert_eq!(library.history.len(), HISTORY_LENGTH);
+        assert_eq!(library.history[0], "10");
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(track_path("file:///music/caf%C3%A9%20noir.mp3"), "/music/café noir.mp3");
+        assert_eq!(track_path("file:///music/100%.mp3"), "/music/100%.mp3");
+        assert_eq!(track_path("http://radio/live"), "http://radio/live");
+        assert!(same_track("/music/jazz/a.mp3", "jazz/a.mp3"));
+        assert!(!same_track("/music/jazz/a.mp3", "z/a.mp3"));
+    }
+}
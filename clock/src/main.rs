@@ -1,3 +1,4 @@
+use std::env;
 use std::sync::mpsc;
 use std::sync::mpsc::channel;
 use std::thread;
@@ -12,47 +13,180 @@ mod keys;
 mod ceiling;
 mod clock_data;
 mod player;
+mod keydiag;
+mod config;
+mod files;
+mod input;
+mod evdev;
+mod buttons;
+mod rotary;
+mod action;
+mod ui;
+mod menu;
+mod settings;
+mod alarm;
+mod schedule;
+mod ical;
+mod agenda;
+mod caldav;
+mod rules;
+mod holidays;
+mod overrides;
+mod status;
+mod last_seen;
+mod sleep;
+mod vlc;
+mod mpv;
+mod mpd;
+mod mock;
+mod stations;
+mod watchdog;
+mod stream;
+mod icy;
+mod timeshift;
+mod library;
+#[cfg(feature = "native")]
+mod native;
+#[cfg(feature = "native")]
+mod sink;
 
 use display::*;
-use keys::*;
 use clock_data::*;
 use ceiling::*;
+use keydiag::*;
+use config::*;
+use input::*;
+use action::*;
+use ui::*;
+use settings::*;
+use alarm::*;
+use agenda::*;
+use holidays::*;
+use overrides::*;
+use status::*;
+use last_seen::*;
+use sleep::*;
 use player::*;
+use stations::*;
+use watchdog::*;
+use timeshift::*;
+use library::*;
+
+const DRY_RUN_DAYS: i64 = 14;
 
 fn main() {
+    if let Some(command) = env::args().nth(1) {
+        match command.as_str() {
+            "keys" => {
+                let gpio = Arc::new(Gpio::new().expect("Cannot open gpio"));
+                keys_diagnostics(gpio).expect("Key diagnostics failed");
+            },
+            "dry-run" => dry_run(),
+            "status" => print_status(),
+            _ => { println!("Usage: clock [keys|dry-run|status]"); exit(1); },
+        }
+        return;
+    }
+
     // init
+    let config = Config::load();
     let (key_tx, main_rx) = channel();
+    let mut player = Player::new(open_backend(&config.player), local_stations(&config.radio), &config.radio.fallback,
+                                 Library::load(&config.state_dir));
     let display_data = Arc::new(Mutex::new(ClockData::new()));
     let gpio = Arc::new(Gpio::new().expect("Cannot open gpio"));
     let mut ceiling = Ceiling::new(gpio.clone(), display_data.clone()).unwrap();
+    let settings = Settings::load(&config.state_dir);
+    settings.apply(&mut display_data.lock().expect("poisoned mutex 6"));
+    player.set_default_volume(settings.volume);
     update_time(&display_data);
-    display_data.lock().expect("poisoned mutex 6").ceiling_upwards = false;
     ceiling.set_time();
+    if let Err(e) = ceiling.set_light() {
+        println!("Ceiling error {:?}", e);
+    }
 
     // spawn threads
     let ddt = display_data.clone();
     let gpio2 = gpio.clone();
     thread::spawn(move || led_display_thread(gpio2, ddt));
-    thread::spawn(move || keys_thread(key_tx, gpio));
-    main_thread(main_rx, display_data);
+    for input_config in &config.inputs {
+        match open_input(input_config, &gpio) {
+            Ok(input) => {
+                let tx = key_tx.clone();
+                thread::spawn(move || input_thread(tx, input));
+            },
+            Err(e) => println!("Cannot open input {:?}: {:?}", input_config, e),
+        }
+    }
+    let bindings = Bindings::new(&config.bindings);
+    let ui = Ui::new(bindings, Duration::from_secs(config.ui.mode_timeout_s), settings.clone(), Instant::now());
+    let days_off = DaysOff::new(&config.days_off);
+    let mut alarms = AlarmClock::new(load_alarms(&config.alarms), days_off.clone(), &config.alarm, Local::now());
+    alarms.set_default_volume(settings.volume);
+    let overrides = Overrides::load(&config.state_dir);
+    alarms.set_overrides(overrides);
+    // agenda alarms are not known yet, we look again when they come, without ringing twice
+    let started = Local::now();
+    let last_seen = load_last_seen(&config.state_dir);
+    if let Some(last_seen) = last_seen {
+        alarms.catch_up(last_seen, started, started);
+    }
+    // playlist urls may take a while
+    let (stations_tx, stations_rx) = channel();
+    let radio = config.radio.clone();
+    thread::spawn(move || stations_tx.send(load_stations(&radio)));
+    let (agenda_tx, agenda_rx) = channel();
+    if let Some(agenda) = Agenda::new(&config.agenda, days_off, &config.state_dir) {
+        let refresh = Duration::from_secs(config.agenda.refresh_minutes * 60);
+        thread::spawn(move || agenda_thread(agenda_tx, agenda, refresh));
+    }
+    let station = player.station().name.clone();
+    let clock = Clock { display_data, ceiling, ui, alarms, state_dir: config.state_dir.clone(),
+                        overrides, status: None, offline: last_seen.map(|l| (l, started)),
+                        sleep: SleepTimer::new(), player, player_state: PlayerState::default(), station,
+                        watchdog: Watchdog::new(&config.radio), scroll_titles: config.ui.scroll_titles,
+                        fallback_missing: false, warming: false, timeshift: Timeshift::new(&config.timeshift) };
+    main_thread(main_rx, agenda_rx, stations_rx, clock);
+}
+
+// alarms the agenda gives for the next days, and why
+fn dry_run() {
+    let config = Config::load();
+    let agenda = match Agenda::new(&config.agenda, DaysOff::new(&config.days_off), &config.state_dir) {
+        Some(agenda) => agenda,
+        None => { println!("No agenda configured"); exit(1); },
+    };
+    match agenda.dry_run(Local::now(), DRY_RUN_DAYS) {
+        Ok(lines) => for line in lines {
+            println!("{}", line);
+        },
+        Err(e) => { println!("Cannot read agenda {:?}", e); exit(1); },
+    }
 }
 
-// thread 1 : handle keystrokes
+// status written by the running clock
+fn print_status() {
+    let config = Config::load();
+    let path = std::path::Path::new(&config.state_dir).join(status::FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => print!("{}", content),
+        Err(e) => { println!("Cannot read {}: {:?}", path.display(), e); exit(1); },
+    }
+}
+
+// thread 1..n : handle keystrokes, one per input
 // thread 2 : handle led matrix
+// thread 3 : read agenda, it can take time when it comes from the network
 // master thread : handle everything else
 
-fn keys_thread(tx: mpsc::Sender<Button>, gpio: Arc<Gpio>) {
-    println!("Keys");
-    let mut keys = Keys::new(gpio).unwrap();
+fn agenda_thread(tx: mpsc::Sender<Vec<Alarm>>, agenda: Agenda, refresh: Duration) {
     loop {
-        // wait for key event
-        // read key via wait on condo charge
-        match keys.poll_button() {
-            Err(e) => println!("Error {:?}", e),
-            Ok(None) => println!("no button"), 
-            // send key to master thread via mspc
-            Ok(Some(button)) => tx.send(button).unwrap(),
+        match agenda.alarms(Local::now()) {
+            Ok(alarms) => tx.send(alarms).unwrap(),
+            // a broken agenda keeps the previous alarms
+            Err(e) => println!("Cannot read agenda {:?}", e),
         }
+        thread::sleep(refresh);
     }
 }
 
@@ -74,32 +208,431 @@ fn update_time(display_data: &Arc<Mutex<ClockData>>) {
     data.minutes = time.format("%M").to_string().parse::<u8>().expect("invalid minute");
 }
 
-fn main_thread(rx: mpsc::Receiver<Button>, display_data: Arc<Mutex<ClockData>>) {
+// everything the master thread works with
+struct Clock {
+    display_data: Arc<Mutex<ClockData>>,
+    ceiling: Ceiling,
+    ui: Ui,
+    alarms: AlarmClock,
+    state_dir: String,
+    // last saved, to save only changes
+    overrides: Overrides,
+    status: Option<Status>,
+    // last seen and started times, until the agenda comes
+    offline: Option<(chrono::DateTime<Local>, chrono::DateTime<Local>)>,
+    sleep: SleepTimer,
+    player: Player,
+    // last known state of the player
+    player_state: PlayerState,
+    // name of the selected station
+    station: String,
+    watchdog: Watchdog,
+    scroll_titles: bool,
+    // seen by the last warm up
+    fallback_missing: bool,
+    // once for each alarm
+    warming: bool,
+    timeshift: Option<Timeshift>,
+}
+
+fn main_thread(rx: mpsc::Receiver<Press>, agenda_rx: mpsc::Receiver<Vec<Alarm>>, stations_rx: mpsc::Receiver<Vec<Station>>,
+               mut clock: Clock) {
     // wait for event : key, timeout
     //
-    // key snooze : snooze
-    // key OnOff: stop / start audio
-    // key spkr+/- : vol +/-
-    // key time : enable/disable alarm
-    // key left/rght: change channel 
-    // key B1 : special mode (+led)
-    // key B2 : special mode 2 ?
+    // key events and ticks drive the ui state machine (see ui.rs for modes),
+    // bound actions are executed here
     //
-    // special key time : enable/disable ceiling
-    // special key left/right : dimm + / -
-    // special key : refresh
-    // special key B1 : normal mode (or wait 1mn)
-    // 
     // timeout : update rwlock time
     // timeout : update top clock
-    // timeout : run radio / fallback
+    // timeout : ring alarm
     // timeout xN : update alarm from calendar
-    let timeout = Duration::from_millis(1000);
+    let tick = Duration::from_millis(1000);
+    let player_poll = Duration::from_secs(10);
+    let mut last_tick = Instant::now();
+    let mut last_poll = Instant::now();
+    let mut minutes = clock.display_data.lock().expect("poisoned mutex 9").minutes;
     loop {
-        match rx.recv_timeout(timeout) {
-            Ok(btn) => println!("button {:?}", btn),
-            Err(mpsc::RecvTimeoutError::Timeout) => update_time(&display_data),
+        let timeout = tick.saturating_sub(last_tick.elapsed());
+        let mut commands = match rx.recv_timeout(timeout) {
+            Ok(press) => {
+                println!("button {:?} in {:?} mode", press, clock.ui.mode());
+                clock.ui.press(press, Instant::now())
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => Vec::new(),
             Err(e) => { println!("Bork {:?}", e); exit(1); },
+        };
+        // keep ticking even when buttons are pressed
+        if last_tick.elapsed() >= tick {
+            last_tick = Instant::now();
+            update_time(&clock.display_data);
+            let now = clock.display_data.lock().expect("poisoned mutex 10").minutes;
+            if now != minutes {
+                minutes = now;
+                clock.ceiling.set_time();
+                if let Err(e) = save_last_seen(&clock.state_dir, Local::now()) {
+                    println!("Cannot save last seen time {:?}", e);
+                }
+            }
+            commands.append(&mut clock.ui.tick(last_tick));
+            if let Ok(stations) = stations_rx.try_recv() {
+                clock.player.set_stations(stations);
+            }
+            if let Ok(alarms) = agenda_rx.try_recv() {
+                clock.alarms.set_agenda(alarms);
+                if let Some((last_seen, started)) = clock.offline.take() {
+                    clock.alarms.catch_up(last_seen, started, Local::now());
+                }
+            }
+            let events = clock.alarms.tick(Local::now());
+            commands.append(&mut clock.alarm_events(events));
+            if let Some(event) = clock.sleep.tick(Local::now()) {
+                clock.sleep_event(event);
+            }
+            let dawn = clock.alarms.dawn(Local::now());
+            clock.set_dawn(dawn);
+            clock.warm_up(Local::now());
+            clock.record(Local::now());
+            if last_poll.elapsed() >= player_poll {
+                last_poll = Instant::now();
+                clock.poll_player();
+            }
+        }
+        for command in commands {
+            clock.run_command(command);
+        }
+        clock.update_indicator();
+        clock.save_state();
+    }
+}
+
+impl Clock {
+    fn run_command(&mut self, command: Command) {
+        match command {
+            Command::Run(action) => self.execute(action),
+            Command::Apply(settings) => {
+                settings.apply(&mut self.display_data.lock().expect("poisoned mutex 13"));
+                self.player.set_default_volume(settings.volume);
+                self.alarms.set_default_volume(settings.volume);
+                self.ceiling.set_time();
+                if let Err(e) = self.ceiling.set_light() {
+                    println!("Ceiling error {:?}", e);
+                }
+                if let Err(e) = settings.save(&self.state_dir) {
+                    println!("Cannot save settings {:?}", e);
+                }
+            },
+            Command::Show(text, blink, duration) =>
+                self.display_data.lock().expect("poisoned mutex 11").overlay = Some(Overlay::new(&text, blink, duration)),
+            Command::ClearOverlay => self.display_data.lock().expect("poisoned mutex 12").overlay = None,
+            Command::Nap(minutes) => {
+                let now = Local::now();
+                let end = if minutes == 0 { None } else { Some(now + chrono::Duration::minutes(minutes as i64)) };
+                self.alarms.set_nap(end);
+                let text = end.map_or("nOFF".to_string(), |t| t.format("%H%M").to_string());
+                self.show(&text);
+            },
+        }
+    }
+
+    fn execute(&mut self, action: Action) {
+        let now = Local::now();
+        let alarm_active = self.alarms.is_active();
+        let events = match action {
+            Action::Snooze => self.alarms.snooze(now),
+            Action::Dismiss => self.alarms.dismiss(),
+            // stopping the radio while the alarm is on means we are awake
+            Action::TogglePlayback if alarm_active => self.alarms.dismiss(),
+            Action::ShowNextAlarm => {
+                self.show_next_alarm(now);
+                Vec::new()
+            },
+            Action::SleepTimer => {
+                self.cycle_sleep_timer(now);
+                Vec::new()
+            },
+            // the alarm after the skipped one is shown when skipping is cancelled
+            Action::SkipNextAlarm => {
+                if self.alarms.skip_next(now) {
+                    self.show("SKIP");
+                } else {
+                    self.show_next_alarm(now);
+                }
+                Vec::new()
+            },
+            Action::ToggleAlarm => {
+                let events = self.alarms.toggle();
+                let text = if self.alarms.is_enabled() { "A on" } else { "AOFF" };
+                self.show(text);
+                events
+            },
+            _ => Vec::new(),
+        };
+        for command in self.alarm_events(events) {
+            self.run_command(command);
+        }
+        let result = match action {
+            Action::TogglePlayback if !alarm_active => match self.sleep.cancel() {
+                Some(volume) => self.player.set_volume(volume).and_then(|_| self.player.toggle()),
+                None => self.player.toggle(),
+            },
+            Action::VolumeUp => self.player.volup(),
+            Action::VolumeDown => self.player.voldown(),
+            Action::NextStation => self.player.change_url(true),
+            Action::PreviousStation => self.player.change_url(false),
+            // other actions are handled by the ui
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            println!("Player error {:?}", e);
+        }
+        if let Action::NextStation | Action::PreviousStation = action {
+            self.station = self.player.station().name.clone();
+            let overlay = Overlay::new(&self.player.station().label, false, Some(FEEDBACK_DURATION));
+            self.display_data.lock().expect("poisoned mutex 8").overlay = Some(overlay);
+        }
+    }
+
+    fn alarm_events(&mut self, events: Vec<AlarmEvent>) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for event in events {
+            println!("alarm {:?}", event);
+            let result = match event {
+                AlarmEvent::Ring(sound) => {
+                    // whatever mode we are in, snooze must work
+                    commands.append(&mut self.ui.reset());
+                    // the alarm has its own volume
+                    self.sleep.cancel();
+                    let player = &mut self.player;
+                    // the recording plays from the last mark, the warm up already chose what plays otherwise,
+                    // the stream or the fallback when it didn't come
+                    let recording = self.timeshift.as_ref().and_then(|t| t.url(Local::now()));
+                    let source = if let Some(url) = recording {
+                        println!("Playing the recording {}", url);
+                        player.play_recording(&url, sound.station)
+                    } else if !player.is_warm() {
+                        sound.station.map_or(Ok(()), |s| player.select(s))
+                    } else if self.player_state.playback != Playback::Playing && !player.is_fallback() {
+                        println!("Warm up failed, playing the fallback file");
+                        player.play_fallback()
+                    } else {
+                        Ok(())
+                    };
+                    player.ring(source, sound.volume)
+                },
+                AlarmEvent::Volume(volume) => self.player.set_volume(volume),
+                AlarmEvent::Escalate(volume) => self.player.play_fallback()
+                    .and_then(|_| self.player.set_volume(volume)),
+                AlarmEvent::Silence => self.player.stop(),
+            };
+            if let Err(e) = result {
+                println!("Player error {:?}", e);
+            }
         }
+        commands
+    }
+
+    fn update_indicator(&mut self) {
+        let indicator = self.alarms.indicator(Local::now());
+        let mut data = self.display_data.lock().expect("poisoned mutex 15");
+        data.alarm_enabled = self.alarms.is_enabled();
+        data.has_alarm = indicator == Indicator::Armed;
+        data.alarm_ringing = indicator == Indicator::Ringing;
+        data.alarm_snoozed = indicator == Indicator::Snoozed;
+        data.alarm_skipped = indicator == Indicator::Skipped;
+        data.nap = self.alarms.overrides().nap.is_some();
+        if self.alarms.missed().is_some() {
+            data.error |= ERROR_MISSED_ALARM;
+        } else {
+            data.error &= !ERROR_MISSED_ALARM;
+        }
+        if self.watchdog.failed() {
+            data.error |= ERROR_STREAM;
+        } else {
+            data.error &= !ERROR_STREAM;
+        }
+        if self.fallback_missing {
+            data.error |= ERROR_FALLBACK;
+        } else {
+            data.error &= !ERROR_FALLBACK;
+        }
+    }
+
+    // the radio plays until the timer ends, a press after the longest duration cancels it
+    fn cycle_sleep_timer(&mut self, now: chrono::DateTime<Local>) {
+        let minutes = self.sleep.cycle(now, self.player.volume());
+        let result = self.player.set_volume(self.sleep.volume())
+            .and_then(|_| if minutes.is_some() { self.player.play() } else { Ok(()) });
+        if let Err(e) = result {
+            println!("Player error {:?}", e);
+        }
+        self.show(&minutes.map_or("SOFF".to_string(), |m| format!("S{:>3}", m)));
+    }
+
+    fn sleep_event(&mut self, event: SleepEvent) {
+        let result = match event {
+            SleepEvent::Volume(volume) => self.player.set_volume(volume),
+            SleepEvent::Stop(volume) => self.player.stop().and_then(|_| self.player.set_volume(volume)),
+        };
+        if let Err(e) = result {
+            println!("Player error {:?}", e);
+        }
+    }
+
+    // the player starts muted before the alarm, unless it is already playing,
+    // and stops when the alarm is not coming anymore
+    fn warm_up(&mut self, now: chrono::DateTime<Local>) {
+        let sound = self.alarms.warm_up(now);
+        let result = match sound {
+            Some(_) if self.warming => Ok(()),
+            Some(sound) => {
+                self.warming = true;
+                if self.player.is_playing() { return }
+                println!("Warming up for the alarm");
+                self.fallback_missing = !self.player.fallback_ok();
+                if self.fallback_missing {
+                    println!("The fallback file is missing");
+                }
+                self.player.warm_up(sound.station)
+            },
+            None => {
+                self.warming = false;
+                if self.player.is_warm() && !self.alarms.is_active() {
+                    println!("No alarm coming, warm up stopped");
+                    self.player.stop()
+                } else {
+                    Ok(())
+                }
+            },
+        };
+        if let Err(e) = result {
+            println!("Player error {:?}", e);
+        }
+    }
+
+    // the alarm station is recorded from a while before the alarm,
+    // until the alarm is over and the recording is not played anymore
+    fn record(&mut self, now: chrono::DateTime<Local>) {
+        let timeshift = match self.timeshift {
+            Some(ref mut timeshift) => timeshift,
+            None => return,
+        };
+        if self.alarms.is_active() { return }
+        let player = &self.player;
+        match self.alarms.coming(now, timeshift.lead()) {
+            Some(sound) => {
+                let station = sound.station.filter(|s| *s < player.station_count()).unwrap_or(player.current());
+                let url = &player.stations()[station].url;
+                // music is not live
+                if Library::is_library(url) {
+                    timeshift.stop();
+                } else {
+                    timeshift.record(url);
+                }
+            },
+            None if !(player.is_playing() && player.is_recorded()) => timeshift.stop(),
+            None => (),
+        }
+    }
+
+    fn poll_player(&mut self) {
+        // alarms select stations too
+        self.station = self.player.station().name.clone();
+        match self.player.state() {
+            Ok(state) => {
+                if state.input != self.player_state.input && self.player.is_playing() {
+                    if let Some(ref input) = state.input {
+                        self.player.played(input);
+                    }
+                }
+                if state.title != self.player_state.title {
+                    if let Some(ref title) = state.title {
+                        println!("Now playing: {}", title);
+                        if self.scroll_titles && self.player.is_playing() {
+                            let mut data = self.display_data.lock().expect("poisoned mutex 20");
+                            // feedback and menus come first
+                            let busy = data.overlay.as_ref().is_some_and(|o| o.until.is_none_or(|u| u > Instant::now()));
+                            if !busy {
+                                data.overlay = Some(Overlay::scrolling(title));
+                            }
+                        }
+                    }
+                }
+                self.player_state = state;
+            },
+            Err(e) => {
+                println!("Player error {:?}", e);
+                self.player_state.playback = Playback::Error;
+            },
+        }
+        let result = match self.watchdog.check(Local::now(), &self.player, &self.player_state, network_up()) {
+            Some(WatchdogEvent::Failover(station)) => {
+                println!("Stream failed, trying station {}", station);
+                self.player.play_station(station)
+            },
+            Some(WatchdogEvent::Fallback) => {
+                println!("Stream failed, playing the fallback file");
+                self.player.play_fallback()
+            },
+            Some(WatchdogEvent::Retry(station)) => {
+                println!("Trying station {} again", station);
+                self.player.play_station(station)
+            },
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            println!("Player error {:?}", e);
+        }
+    }
+
+    fn set_dawn(&mut self, dawn: Option<f64>) {
+        {
+            let mut data = self.display_data.lock().expect("poisoned mutex 17");
+            if data.dawn == dawn { return }
+            data.dawn = dawn;
+        }
+        if let Err(e) = self.ceiling.set_light() {
+            println!("Ceiling error {:?}", e);
+        }
+    }
+
+    // overrides and status, when they changed
+    fn save_state(&mut self) {
+        let now = Local::now();
+        let overrides = self.alarms.overrides();
+        if overrides != self.overrides {
+            self.overrides = overrides;
+            if let Err(e) = overrides.save(&self.state_dir) {
+                println!("Cannot save overrides {:?}", e);
+            }
+        }
+        let status = Status {
+            alarms_enabled: self.alarms.is_enabled(),
+            alarm: self.alarms.indicator(now),
+            next_alarm: self.alarms.next_alarm(now).map(|(t, _)| t),
+            skipped: overrides.skipped,
+            nap: overrides.nap,
+            missed: overrides.missed,
+            sleep: self.sleep.end(),
+            player: self.player_state.clone(),
+            station: self.station.clone(),
+        };
+        if self.status.as_ref() != Some(&status) {
+            if let Err(e) = status.save(&self.state_dir) {
+                println!("Cannot save status {:?}", e);
+            }
+            self.status = Some(status);
+        }
+    }
+
+    fn show_next_alarm(&mut self, now: chrono::DateTime<Local>) {
+        let text = match self.alarms.next_alarm(now) {
+            Some((time, _)) => time.format("%H%M").to_string(),
+            None => "nonE".to_string(),
+        };
+        self.show(&text);
+    }
+
+    fn show(&mut self, text: &str) {
+        self.display_data.lock().expect("poisoned mutex 16").overlay = Some(Overlay::new(text, false, Some(FEEDBACK_DURATION)));
     }
 }
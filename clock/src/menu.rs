@@ -0,0 +1,157 @@
+use crate::action::Action;
+use crate::settings::*;
+use crate::ui::Command;
+
+/* Settings menu
+ *
+ *  Left/Right : previous/next setting        while editing : decrease/increase value
+ *  Time       : edit the setting             while editing : save the value
+ *  Snooze     : leave the menu               while editing : cancel
+ *
+ * The label of the current setting is shown, the value blinks while it is edited.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Item {
+    RegularDim, CeilingDim, RefreshRate, CeilingEnabled, CeilingUpwards, Volume
+}
+
+const ITEMS: [Item; 6] = [
+    Item::RegularDim, Item::CeilingDim, Item::RefreshRate, Item::CeilingEnabled, Item::CeilingUpwards, Item::Volume
+];
+
+impl Item {
+    fn label(&self) -> &'static str {
+        match self {
+            Item::RegularDim => "Led",
+            Item::CeilingDim => "CEIL",
+            Item::RefreshRate => "rAtE",
+            Item::CeilingEnabled => "C on",
+            Item::CeilingUpwards => "FLIP",
+            Item::Volume => "UOL",
+        }
+    }
+
+    fn value(&self, settings: &Settings) -> String {
+        let yes_no = |b| if b { " yES" } else { "  no" }.to_string();
+        match self {
+            Item::RegularDim => format!("{:>4}", settings.regular_dim),
+            Item::CeilingDim => format!("{:>4}", settings.ceiling_dim),
+            Item::RefreshRate => format!("{:>4}", settings.refresh_rate),
+            Item::CeilingEnabled => yes_no(settings.ceiling_enabled),
+            Item::CeilingUpwards => yes_no(settings.ceiling_upwards),
+            Item::Volume => format!("{:>4}", settings.volume),
+        }
+    }
+
+    fn step(&self, settings: &mut Settings, up: bool) {
+        match self {
+            Item::RegularDim => settings.regular_dim = step_dim(settings.regular_dim, up),
+            Item::CeilingDim => settings.ceiling_dim = step_dim(settings.ceiling_dim, up),
+            Item::RefreshRate => settings.refresh_rate = step_refresh(settings.refresh_rate, up),
+            Item::CeilingEnabled => settings.ceiling_enabled = !settings.ceiling_enabled,
+            Item::CeilingUpwards => settings.ceiling_upwards = !settings.ceiling_upwards,
+            Item::Volume => settings.volume = if up {
+                settings.volume.saturating_add(VOLUME_STEP).min(MAX_VOLUME)
+            } else {
+                settings.volume.saturating_sub(VOLUME_STEP)
+            },
+        }
+    }
+}
+
+pub fn step_dim(dim: u8, up: bool) -> u8 {
+    if up { dim.saturating_add(DIM_STEP).min(100) } else { dim.saturating_sub(DIM_STEP) }
+}
+
+pub fn step_refresh(rate: u32, up: bool) -> u32 {
+    if up { rate.saturating_add(REFRESH_STEP).min(MAX_REFRESH) } else { rate.saturating_sub(REFRESH_STEP).max(MIN_REFRESH) }
+}
+
+pub struct Menu {
+    position: usize,
+    // settings being edited, not applied yet
+    edited: Option<Settings>,
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Menu { position: 0, edited: None }
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.edited.is_some()
+    }
+
+    // start from the first item
+    pub fn open(&mut self) -> Vec<Command> {
+        self.position = 0;
+        self.edited = None;
+        self.show_label()
+    }
+
+    // forget any unsaved value
+    pub fn close(&mut self) {
+        self.edited = None;
+    }
+
+    pub fn action(&mut self, action: Action, settings: &mut Settings) -> Vec<Command> {
+        let item = ITEMS[self.position];
+        match (self.edited.as_mut(), action) {
+            (None, Action::MenuPrevious) => {
+                self.position = (self.position + ITEMS.len() - 1) % ITEMS.len();
+                self.show_label()
+            },
+            (None, Action::MenuNext) => {
+                self.position = (self.position + 1) % ITEMS.len();
+                self.show_label()
+            },
+            (None, Action::MenuSelect) => {
+                let edited = settings.clone();
+                let commands = show_value(item, &edited);
+                self.edited = Some(edited);
+                commands
+            },
+            (Some(edited), Action::MenuPrevious) | (Some(edited), Action::MenuNext) => {
+                item.step(edited, action == Action::MenuNext);
+                show_value(item, edited)
+            },
+            (Some(_), Action::MenuSelect) => {
+                *settings = self.edited.take().unwrap();
+                let mut commands = vec![Command::Apply(settings.clone())];
+                commands.append(&mut self.show_label());
+                commands
+            },
+            (Some(_), Action::MenuBack) => {
+                self.edited = None;
+                self.show_label()
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn show_label(&self) -> Vec<Command> {
+        vec![Command::Show(ITEMS[self.position].label().into(), false, None)]
+    }
+}
+
+fn show_value(item: Item, settings: &Settings) -> Vec<Command> {
+    vec![Command::Show(item.value(settings), true, None)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        assert_eq!(step_dim(95, true), 100);
+        assert_eq!(step_dim(250, true), 100);
+        assert_eq!(step_dim(5, false), 0);
+        assert_eq!(step_refresh(MAX_REFRESH, true), MAX_REFRESH);
+        assert_eq!(step_refresh(MIN_REFRESH + 5, false), MIN_REFRESH);
+        let mut settings = Settings { volume: u16::MAX, ..Settings::default() };
+        Item::Volume.step(&mut settings, true);
+        assert_eq!(settings.volume, MAX_VOLUME);
+    }
+}
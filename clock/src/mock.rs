@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::io::{Error, Result};
+use std::sync::{Arc, Mutex};
+
+use crate::player::*;
+
+/* Player without sound, to run the clock without audio and to try the rest of it
+ *
+ * Commands are printed and the state follows them.
+ * A script of playback states can be given: each state query takes the next one,
+ * then the mock plays normally. An error in the script also fails the next command.
+ */
+
+pub struct Mock {
+    started: bool,
+    playlist: Vec<String>,
+    state: PlayerState,
+    script: VecDeque<Playback>,
+    failing: bool,
+    // commands in order, kept only for tests
+    commands: Option<Arc<Mutex<Vec<String>>>>,
+}
+
+impl Mock {
+    pub fn new(script: &[Playback]) -> Self {
+        Mock {
+            started: false,
+            playlist: Vec::new(),
+            state: PlayerState::default(),
+            script: script.iter().copied().collect(),
+            failing: false,
+            commands: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn commands(&mut self) -> Arc<Mutex<Vec<String>>> {
+        self.commands.get_or_insert_with(Default::default).clone()
+    }
+
+    fn command(&mut self, command: String) -> Result<()> {
+        println!("Mock player {}", command);
+        if let Some(ref commands) = self.commands {
+            commands.lock().expect("poisoned mutex 30").push(command.clone());
+        }
+        if self.failing {
+            self.failing = false;
+            return Err(Error::other(format!("mock failure on {}", command)));
+        }
+        Ok(())
+    }
+}
+
+impl Backend for Mock {
+    fn start(&mut self) -> Result<bool> {
+        if self.started { return Ok(false) }
+        self.started = true;
+        self.command("start".to_string())?;
+        Ok(true)
+    }
+
+    fn load(&mut self, urls: &[&str], play: bool) -> Result<()> {
+        self.command(format!("load {:?} {}", urls, if play { "playing" } else { "stopped" }))?;
+        self.playlist = urls.iter().map(|u| u.to_string()).collect();
+        self.state.input = self.playlist.first().cloned();
+        self.state.playback = if play { Playback::Playing } else { Playback::Stopped };
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.command("play".to_string())?;
+        self.state.playback = Playback::Playing;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.command("stop".to_string())?;
+        self.state.playback = Playback::Stopped;
+        Ok(())
+    }
+
+    fn set_volume(&mut self, volume: u16) -> Result<()> {
+        self.command(format!("volume {}", volume))?;
+        self.state.volume = Some(volume);
+        Ok(())
+    }
+
+    fn state(&mut self) -> Result<PlayerState> {
+        if let Some(playback) = self.script.pop_front() {
+            self.state.playback = playback;
+            self.failing = playback == Playback::Error;
+        }
+        Ok(self.state.clone())
+    }
+}
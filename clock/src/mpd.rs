@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::*;
+
+use crate::player::*;
+
+/* MPD backend, the music player daemon protocol over tcp
+ *
+ *   OK MPD 0.23.5                  greeting
+ *   setvol 50                      command, arguments "quoted" when needed
+ *   volume: 50                     answer, key: value lines
+ *   OK                             or ACK [error@line] {command} message
+ *
+ * mpd runs on its own, it closes idle connections: we connect again when needed.
+ * When it can't be reached it may have restarted, the playlist is set up again.
+ */
+
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub struct Mpd {
+    address: String,
+    stream: Option<BufReader<TcpStream>>,
+    // mpd keeps its playlist when we connect again, it is set up once
+    started: bool,
+}
+
+fn quote(argument: &str) -> String {
+    format!("\"{}\"", argument.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Mpd {
+    pub fn new(address: &str) -> Self {
+        Mpd { address: address.to_string(), stream: None, started: false }
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        let stream = match self.open() {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.started = false;
+                return Err(e);
+            },
+        };
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut stream = BufReader::new(stream);
+        let mut greeting = String::new();
+        stream.read_line(&mut greeting)?;
+        if !greeting.starts_with("OK MPD") {
+            return Err(Error::new(ErrorKind::InvalidData, format!("not mpd: {}", greeting.trim())));
+        }
+        self.stream = Some(stream);
+        // the connection may have been closed by an mpd restart
+        if self.started {
+            self.exchange("repeat 1")?;
+        }
+        Ok(())
+    }
+
+    // an unreachable host must not block the clock
+    fn open(&self) -> Result<TcpStream> {
+        let mut error = Error::new(ErrorKind::NotFound, format!("no address for {}", self.address));
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    // answer lines, once again on a new connection when the old one was closed
+    fn command(&mut self, command: &str) -> Result<Vec<String>> {
+        if self.stream.is_none() {
+            self.connect()?;
+        }
+        match self.exchange(command) {
+            Err(e) if e.kind() != ErrorKind::Other => {
+                self.connect()?;
+                self.exchange(command)
+            },
+            result => result,
+        }
+    }
+
+    fn exchange(&mut self, command: &str) -> Result<Vec<String>> {
+        let stream = self.stream.as_mut()
+            .ok_or_else(|| Error::new(ErrorKind::NotConnected, "not connected to mpd"))?;
+        println!("Sending {}", command);
+        writeln!(stream.get_mut(), "{}", command)?;
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line)? == 0 {
+                self.stream = None;
+                return Err(Error::new(ErrorKind::UnexpectedEof, "mpd closed the connection"));
+            }
+            let line = line.trim_end();
+            if line == "OK" { return Ok(lines) }
+            if line.starts_with("ACK") { return Err(Error::other(line.to_string())) }
+            lines.push(line.to_string());
+        }
+    }
+
+    fn values(&mut self, command: &str) -> Result<HashMap<String, String>> {
+        Ok(self.command(command)?.iter()
+            .filter_map(|l| l.split_once(": "))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect())
+    }
+}
+
+impl Backend for Mpd {
+    fn start(&mut self) -> Result<bool> {
+        if self.started { return Ok(false) }
+        self.command("repeat 1")?;
+        self.started = true;
+        Ok(true)
+    }
+
+    fn load(&mut self, urls: &[&str], play: bool) -> Result<()> {
+        self.command("clear")?;
+        // a missing file must not keep the others from playing
+        let mut queued = 0;
+        for url in urls {
+            match self.command(&format!("add {}", quote(url))) {
+                Ok(_) => queued += 1,
+                Err(e) => println!("Cannot queue {} {:?}", url, e),
+            }
+        }
+        if queued == 0 {
+            return Err(Error::new(ErrorKind::NotFound, "nothing could be queued"));
+        }
+        if play {
+            self.command("play 0")?;
+        }
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.command("play").map(|_| ())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.command("stop").map(|_| ())
+    }
+
+    fn set_volume(&mut self, volume: u16) -> Result<()> {
+        let percent = (volume as u32 * 100 / 256).min(100);
+        self.command(&format!("setvol {}", percent)).map(|_| ())
+    }
+
+    fn state(&mut self) -> Result<PlayerState> {
+        let status = self.values("status")?;
+        let song = self.values("currentsong")?;
+        let playback = match status.get("state").map(|s| s.as_str()) {
+            _ if status.contains_key("error") => Playback::Error,
+            Some("play") => Playback::Playing,
+            Some("pause") => Playback::Paused,
+            _ => Playback::Stopped,
+        };
+        Ok(PlayerState {
+            playback,
+            volume: status.get("volume").and_then(|v| v.parse::<u32>().ok()).map(|v| (v * 256 / 100) as u16),
+            input: song.get("file").cloned(),
+            title: song.get("Title").or_else(|| song.get("Name")).cloned(),
+            position: status.get("elapsed").and_then(|t| t.parse::<f64>().ok()).map(|t| t as u64),
+            silence: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // answers like mpd, keeps the commands, hangs up after some commands like an idle mpd
+    fn server(per_connection: usize) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = commands.clone();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let mut socket = socket.unwrap();
+                let mut reader = BufReader::new(socket.try_clone().unwrap());
+                if socket.write_all(b"OK MPD 0.23.5\n").is_err() { continue }
+                for _ in 0..per_connection {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 { break }
+                    let command = line.trim_end().to_string();
+                    let answer = match command.as_str() {
+                        c if c.starts_with("add \"missing") => "ACK [50@0] {add} No such directory\n",
+                        "status" => "volume: 50\nstate: play\nelapsed: 12.5\nOK\n",
+                        "currentsong" => "file: http://radio/fip\nName: FIP\nOK\n",
+                        _ => "OK\n",
+                    };
+                    received.lock().unwrap().push(command);
+                    if socket.write_all(answer.as_bytes()).is_err() { break }
+                }
+            }
+        });
+        (address, commands)
+    }
+
+    #[test]
+    fn quoted() {
+        assert_eq!(quote(r#"/music/a "b"\c.mp3"#), r#""/music/a \"b\"\\c.mp3""#);
+    }
+
+    #[test]
+    fn missing_files_skipped() {
+        let (address, commands) = server(100);
+        let mut mpd = Mpd::new(&address);
+        assert!(mpd.start().unwrap());
+        assert!(!mpd.start().unwrap());
+        mpd.load(&["missing.mp3", "http://radio/fip"], true).unwrap();
+        assert_eq!(*commands.lock().unwrap(), vec![
+            "repeat 1", "clear", "add \"missing.mp3\"", "add \"http://radio/fip\"", "play 0",
+        ]);
+        assert!(mpd.load(&["missing.mp3"], true).is_err());
+        assert_eq!(commands.lock().unwrap().last().unwrap(), "add \"missing.mp3\"");
+    }
+
+    #[test]
+    fn state() {
+        let (address, _) = server(100);
+        let mut mpd = Mpd::new(&address);
+        assert_eq!(mpd.state().unwrap(), PlayerState {
+            playback: Playback::Playing,
+            volume: Some(128),
+            input: Some("http://radio/fip".to_string()),
+            title: Some("FIP".to_string()),
+            position: Some(12),
+            silence: None,
+        });
+    }
+
+    #[test]
+    fn reconnect() {
+        let (address, commands) = server(2);
+        let mut mpd = Mpd::new(&address);
+        mpd.start().unwrap();
+        mpd.set_volume(256).unwrap();
+        mpd.stop().unwrap();
+        mpd.play().unwrap();
+        // repeat is set again on each connection
+        assert_eq!(*commands.lock().unwrap(), vec!["repeat 1", "setvol 100", "repeat 1", "stop", "repeat 1", "play"]);
+    }
+
+    #[test]
+    fn unreachable() {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let mut mpd = Mpd::new(&address);
+        assert!(mpd.start().is_err());
+        // set up again once it is back
+        mpd.started = true;
+        assert!(mpd.play().is_err());
+        assert!(!mpd.started);
+    }
+}
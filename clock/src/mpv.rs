@@ -0,0 +1,233 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::os::unix::net::UnixStream;
+use std::process::*;
+use std::thread::sleep;
+use std::time::*;
+
+use crate::player::*;
+use crate::settings::MAX_VOLUME;
+
+/* mpv backend, through its json ipc socket
+ *
+ * mpv is started idle with a socket, each command is a json line with an id
+ *   {"command": ["loadfile", "http://...", "replace"], "request_id": 1}
+ * and its answer has the same id
+ *   {"request_id": 1, "error": "success", "data": null}
+ * Events come on the same socket, they are skipped.
+ */
+
+const TIMEOUT: Duration = Duration::from_millis(1000);
+// how long mpv may take to create its socket
+const STARTUP: Duration = Duration::from_secs(3);
+
+// mpv volumes are percents, 256 is 100%
+fn mpv_volume(volume: u16) -> f64 {
+    volume as f64 * 100. / 256.
+}
+
+pub struct Mpv {
+    socket: String,
+    process: Option<Child>,
+    stream: Option<BufReader<UnixStream>>,
+    request_id: u64,
+}
+
+impl Mpv {
+    pub fn new(socket: &str) -> Self {
+        Mpv { socket: socket.to_string(), process: None, stream: None, request_id: 0 }
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        let deadline = Instant::now() + STARTUP;
+        loop {
+            match UnixStream::connect(&self.socket) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(TIMEOUT))?;
+                    self.stream = Some(BufReader::new(stream));
+                    return Ok(());
+                },
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                Err(_) => sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    fn command(&mut self, command: Value) -> Result<Value> {
+        self.request_id += 1;
+        let id = self.request_id;
+        let stream = self.stream.as_mut()
+            .ok_or_else(|| Error::new(ErrorKind::NotConnected, "mpv is not running"))?;
+        let line = json!({ "command": command, "request_id": id }).to_string();
+        println!("Sending {}", line);
+        writeln!(stream.get_mut(), "{}", line)?;
+        loop {
+            let mut answer = String::new();
+            if stream.read_line(&mut answer)? == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "mpv is gone"));
+            }
+            let answer: Value = serde_json::from_str(&answer)?;
+            if answer["request_id"] != id { continue }
+            if answer["error"] != "success" {
+                return Err(Error::other(format!("mpv {}: {}", command, answer["error"])));
+            }
+            return Ok(answer["data"].clone());
+        }
+    }
+
+    fn property(&mut self, name: &str) -> Result<Value> {
+        self.command(json!(["get_property", name]))
+    }
+}
+
+impl Backend for Mpv {
+    fn start(&mut self) -> Result<bool> {
+        if let Some(ref mut process) = self.process {
+            if process.try_wait()?.is_none() && self.stream.is_some() { return Ok(false) }
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+        self.stream = None;
+        self.process = Some(Command::new("mpv")
+                .arg("--idle=yes")
+                .arg("--no-video")
+                .arg("--loop-playlist=inf")
+                .arg(format!("--input-ipc-server={}", self.socket))
+                // the default maximum is 130%, the clock goes up to 200%
+                .arg(format!("--volume-max={}", mpv_volume(MAX_VOLUME)))
+                .stdin(Stdio::null())
+                .spawn()?);
+        self.connect()?;
+        Ok(true)
+    }
+
+    fn load(&mut self, urls: &[&str], play: bool) -> Result<()> {
+        for (i, url) in urls.iter().enumerate() {
+            let mode = if i == 0 { "replace" } else { "append" };
+            self.command(json!(["loadfile", url, mode]))?;
+        }
+        if play {
+            self.command(json!(["set_property", "pause", false]))?;
+            Ok(())
+        } else {
+            self.stop()
+        }
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.command(json!(["set_property", "pause", false]))?;
+        // stopped, start the playlist again
+        if self.property("idle-active")? == true {
+            self.command(json!(["playlist-play-index", 0]))?;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.command(json!(["stop", "keep-playlist"]))?;
+        Ok(())
+    }
+
+    fn set_volume(&mut self, volume: u16) -> Result<()> {
+        self.command(json!(["set_property", "volume", mpv_volume(volume.min(MAX_VOLUME))]))?;
+        Ok(())
+    }
+
+    fn state(&mut self) -> Result<PlayerState> {
+        if self.stream.is_none() { return Ok(PlayerState::default()) }
+        let playback = if self.property("idle-active")? == true {
+            Playback::Stopped
+        } else if self.property("paused-for-cache")? == true {
+            Playback::Buffering
+        } else if self.property("pause")? == true {
+            Playback::Paused
+        } else {
+            Playback::Playing
+        };
+        let text = |v: Value| v.as_str().map(|s| s.to_string());
+        Ok(PlayerState {
+            playback,
+            volume: self.property("volume")?.as_f64().map(|v| (v * 256. / 100.).round() as u16),
+            // nothing playing is an error for mpv
+            input: self.property("path").ok().and_then(text),
+            title: self.property("media-title").ok().and_then(text),
+            position: self.property("time-pos").ok().and_then(|v| v.as_f64()).map(|t| t as u64),
+            silence: None,
+        })
+    }
+}
+
+impl Drop for Mpv {
+    fn drop(&mut self) {
+        if let Some(ref mut process) = self.process {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // answers like a playing mpv, with events in between, keeps the commands
+    fn server(socket: &str) -> Arc<Mutex<Vec<Value>>> {
+        let _ = std::fs::remove_file(socket);
+        let listener = UnixListener::bind(socket).unwrap();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = commands.clone();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            for line in reader.lines() {
+                let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                let command = request["command"].clone();
+                let data = match command[1].as_str() {
+                    _ if command[0] != "get_property" => Value::Null,
+                    Some("volume") => json!(50.),
+                    Some("path") => json!("http://radio/fip"),
+                    Some("media-title") => json!("FIP"),
+                    Some("time-pos") => json!(12.5),
+                    _ => json!(false),
+                };
+                received.lock().unwrap().push(command);
+                writeln!(stream, "{}", json!({ "event": "property-change" })).unwrap();
+                writeln!(stream, "{}", json!({ "request_id": request["request_id"], "error": "success", "data": data })).unwrap();
+            }
+        });
+        commands
+    }
+
+    #[test]
+    fn commands() {
+        let socket = std::env::temp_dir().join("clock-mpv-test.sock");
+        let socket = socket.to_str().unwrap();
+        let commands = server(socket);
+        let mut mpv = Mpv::new(socket);
+        assert_eq!(mpv.state().unwrap(), PlayerState::default());
+        mpv.connect().unwrap();
+        mpv.load(&["http://radio/fip", "fallback.mp3"], true).unwrap();
+        mpv.set_volume(128).unwrap();
+        // the top of the range, within --volume-max
+        mpv.set_volume(MAX_VOLUME).unwrap();
+        assert_eq!(*commands.lock().unwrap(), vec![
+            json!(["loadfile", "http://radio/fip", "replace"]),
+            json!(["loadfile", "fallback.mp3", "append"]),
+            json!(["set_property", "pause", false]),
+            json!(["set_property", "volume", 50.]),
+            json!(["set_property", "volume", 200.]),
+        ]);
+        assert_eq!(mpv.state().unwrap(), PlayerState {
+            playback: Playback::Playing,
+            volume: Some(128),
+            input: Some("http://radio/fip".to_string()),
+            title: Some("FIP".to_string()),
+            position: Some(12),
+            silence: None,
+        });
+        std::fs::remove_file(socket).unwrap();
+    }
+}
@@ -0,0 +1,579 @@
+use std::io::{Error, ErrorKind, Read, Result};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use symphonia::core::codecs::audio::AudioDecoderOptions;
+use symphonia::core::codecs::CodecParameters;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::probe::Hint;
+use symphonia::core::formats::{FormatOptions, TrackType};
+use symphonia::core::io::{MediaSourceStream, ReadOnlySource};
+use symphonia::core::meta::MetadataOptions;
+
+use crate::config::NativeOutput;
+use crate::icy::IcyReader;
+use crate::player::{Backend, Playback, PlayerState};
+use crate::sink::{open_sink, Sink};
+use crate::stream;
+
+/* Native backend of the player
+ *
+ * Everything happens in the clock process: a thread fetches the stream,
+ * another one decodes it with symphonia and writes the samples to the output
+ * with the volume applied. The backend only sends commands to the decoding
+ * thread and reads the state it keeps up to date, with the icy titles.
+ * The level of the decoded samples tells a stream playing silence.
+ */
+
+// chunks read ahead of the decoder, about a minute of a 128kb/s stream
+const READ_AHEAD: usize = 64;
+const CHUNK_SIZE: usize = 16 * 1024;
+// waiting for data, commands are checked this often
+const WAIT: Duration = Duration::from_millis(100);
+// peak of a silent packet, before the volume, about -54 dB
+const SILENCE_LEVEL: i32 = 64;
+
+enum Control {
+    Load(Vec<String>, bool),
+    Play,
+    Stop,
+}
+
+// what the decoding thread shares with the backend
+struct Shared {
+    state: Mutex<PlayerState>,
+    volume: AtomicU16,
+    // commands sent and not received yet, they interrupt the stream
+    pending: AtomicUsize,
+}
+
+impl Shared {
+    fn pending(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) > 0
+    }
+
+    fn update(&self, f: impl FnOnce(&mut PlayerState)) {
+        f(&mut self.state.lock().expect("poisoned native state"))
+    }
+}
+
+pub struct Native {
+    output: NativeOutput,
+    device: String,
+    path: String,
+    shared: Arc<Shared>,
+    control: Option<Sender<Control>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Native {
+    pub fn new(output: NativeOutput, device: &str, path: &str) -> Self {
+        let shared = Shared { state: Mutex::new(PlayerState::default()), volume: AtomicU16::new(256), pending: AtomicUsize::new(0) };
+        Native {
+            output,
+            device: device.to_string(),
+            path: path.to_string(),
+            shared: Arc::new(shared),
+            control: None,
+            thread: None,
+        }
+    }
+
+    fn send(&mut self, control: Control) -> Result<()> {
+        let sender = self.control.as_ref().ok_or_else(|| Error::new(ErrorKind::NotConnected, "native player is not running"))?;
+        self.shared.pending.fetch_add(1, Ordering::SeqCst);
+        sender.send(control).map_err(|_| {
+            self.shared.pending.fetch_sub(1, Ordering::SeqCst);
+            Error::new(ErrorKind::BrokenPipe, "native player is gone")
+        })
+    }
+}
+
+impl Backend for Native {
+    fn start(&mut self) -> Result<bool> {
+        if self.thread.as_ref().is_some_and(|t| !t.is_finished()) { return Ok(false) }
+        let (sender, receiver) = channel();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(PlayerState::default()),
+            volume: AtomicU16::new(self.shared.volume.load(Ordering::SeqCst)),
+            pending: AtomicUsize::new(0),
+        });
+        let sink = open_sink(self.output, &self.device, &self.path);
+        let thread_shared = shared.clone();
+        self.thread = Some(thread::Builder::new()
+            .name("native player".to_string())
+            .spawn(move || run(receiver, thread_shared, sink))?);
+        self.shared = shared;
+        self.control = Some(sender);
+        Ok(true)
+    }
+
+    fn load(&mut self, urls: &[&str], play: bool) -> Result<()> {
+        self.send(Control::Load(urls.iter().map(|u| u.to_string()).collect(), play))
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.send(Control::Play)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.send(Control::Stop)
+    }
+
+    fn set_volume(&mut self, volume: u16) -> Result<()> {
+        self.shared.volume.store(volume, Ordering::SeqCst);
+        self.shared.update(|s| s.volume = Some(volume));
+        Ok(())
+    }
+
+    fn state(&mut self) -> Result<PlayerState> {
+        Ok(self.shared.state.lock().expect("poisoned native state").clone())
+    }
+}
+
+enum Outcome {
+    // true when something was played
+    Ended(bool),
+    Failed(Error),
+    Control(Control),
+    // the backend is gone
+    Quit,
+}
+
+// decoding thread, it loops on the playlist like the other players
+fn run(receiver: Receiver<Control>, shared: Arc<Shared>, mut sink: Box<dyn Sink>) {
+    let mut playlist: Vec<String> = Vec::new();
+    let mut index = 0;
+    let mut playing = false;
+    // entries failing in a row
+    let mut failures = 0;
+    let mut next: Option<Control> = None;
+    loop {
+        let control = match next.take() {
+            Some(control) => Some(control),
+            None if !playing || shared.pending() => {
+                sink.close();
+                match receiver.recv() {
+                    Ok(control) => {
+                        shared.pending.fetch_sub(1, Ordering::SeqCst);
+                        Some(control)
+                    },
+                    Err(_) => return,
+                }
+            },
+            None => None,
+        };
+        let changed = control.is_some();
+        match control {
+            Some(Control::Load(urls, play)) => {
+                playlist = urls;
+                index = 0;
+                failures = 0;
+                playing = play && !playlist.is_empty();
+            },
+            Some(Control::Play) => {
+                failures = 0;
+                playing = !playlist.is_empty();
+            },
+            Some(Control::Stop) => playing = false,
+            None => (),
+        }
+        if !playing {
+            if changed {
+                shared.update(|s| { s.playback = Playback::Stopped; s.position = None; s.title = None; s.silence = None; });
+            }
+            continue;
+        }
+        if changed { continue }
+
+        let url = playlist[index].clone();
+        match play_entry(&url, &receiver, &shared, sink.as_mut()) {
+            Outcome::Control(control) => next = Some(control),
+            Outcome::Quit => return,
+            Outcome::Ended(true) => {
+                failures = 0;
+                index = (index + 1) % playlist.len();
+            },
+            outcome => {
+                if let Outcome::Failed(e) = outcome {
+                    println!("Cannot play {} {:?}", url, e);
+                }
+                failures += 1;
+                index = (index + 1) % playlist.len();
+                // nothing in the playlist plays
+                if failures >= playlist.len() {
+                    playing = false;
+                    failures = 0;
+                    sink.close();
+                    shared.update(|s| { s.playback = Playback::Error; s.position = None; s.title = None; s.silence = None; });
+                }
+            },
+        }
+    }
+}
+
+fn play_entry(url: &str, receiver: &Receiver<Control>, shared: &Arc<Shared>, sink: &mut dyn Sink) -> Outcome {
+    shared.update(|s| {
+        s.playback = Playback::Buffering;
+        s.input = Some(url.to_string());
+        s.title = None;
+        s.position = None;
+        s.silence = None;
+    });
+    let stream = match stream::open(url) {
+        Ok(stream) => stream,
+        Err(e) => return Outcome::Failed(e),
+    };
+    let mut hint = Hint::new();
+    if let Some(ref content_type) = stream.content_type {
+        hint.mime_type(content_type);
+    }
+    if let Some(extension) = url.split(['?', '#']).next().and_then(|p| p.rsplit_once('.')).map(|(_, e)| e).filter(|e| !e.contains('/')) {
+        hint.with_extension(extension);
+    }
+    let reader = match stream.metaint {
+        Some(metaint) => {
+            let titles = shared.clone();
+            Box::new(IcyReader::new(stream.reader, metaint, move |title| titles.update(|s| s.title = title)))
+        },
+        None => stream.reader,
+    };
+    let source = ReadOnlySource::new(Prefetch::new(reader, shared.clone()));
+    let source = MediaSourceStream::new(Box::new(source), Default::default());
+
+    let mut format = match symphonia::default::get_probe().probe(&hint, source, FormatOptions::default(), MetadataOptions::default()) {
+        Ok(format) => format,
+        Err(e) => return interrupted(receiver, shared).unwrap_or(Outcome::Failed(Error::other(e))),
+    };
+    let (track_id, params) = match format.default_track(TrackType::Audio) {
+        Some(track) => match track.codec_params {
+            Some(CodecParameters::Audio(ref params)) => (track.id, params.clone()),
+            _ => return Outcome::Failed(Error::new(ErrorKind::InvalidData, "no audio codec")),
+        },
+        None => return Outcome::Failed(Error::new(ErrorKind::InvalidData, "no audio track")),
+    };
+    let mut decoder = match symphonia::default::get_codecs().make_audio_decoder(&params, &AudioDecoderOptions::default()) {
+        Ok(decoder) => decoder,
+        Err(e) => return Outcome::Failed(Error::other(e)),
+    };
+
+    let mut frames: u64 = 0;
+    let mut silent_frames: u64 = 0;
+    let mut samples: Vec<i16> = Vec::new();
+    loop {
+        if let Some(outcome) = interrupted(receiver, shared) { return outcome }
+        let packet = match format.next_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => return Outcome::Ended(frames > 0),
+            Err(DecodeError::IoError(ref e)) if e.kind() == ErrorKind::UnexpectedEof => return Outcome::Ended(frames > 0),
+            Err(e) => return interrupted(receiver, shared).unwrap_or(Outcome::Failed(Error::other(e))),
+        };
+        if packet.track_id != track_id { continue }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // a damaged packet is skipped
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Outcome::Failed(Error::other(e)),
+        };
+        let rate = decoded.spec().rate();
+        let channels = decoded.spec().channels().count();
+        decoded.copy_to_vec_interleaved(&mut samples);
+        // heard before the volume, a muted warm up is not silent
+        let packet_frames = (samples.len() / channels.max(1)) as u64;
+        silent_frames = if is_silent(&samples) { silent_frames + packet_frames } else { 0 };
+        let volume = shared.volume.load(Ordering::SeqCst) as i32;
+        for sample in samples.iter_mut() {
+            *sample = (*sample as i32 * volume / 256).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+        if let Err(e) = sink.write(rate, channels, &samples) {
+            return Outcome::Failed(e);
+        }
+        frames += packet_frames;
+        let position = frames / rate.max(1) as u64;
+        let silence = silent_frames / rate.max(1) as u64;
+        shared.update(|s| { s.playback = Playback::Playing; s.position = Some(position); s.silence = Some(silence); });
+    }
+}
+
+fn is_silent(samples: &[i16]) -> bool {
+    samples.iter().all(|s| (*s as i32).abs() < SILENCE_LEVEL)
+}
+
+// a command that stops the current entry, play changes nothing while playing
+fn interrupted(receiver: &Receiver<Control>, shared: &Shared) -> Option<Outcome> {
+    while shared.pending() {
+        let control = match receiver.recv() {
+            Ok(control) => control,
+            Err(_) => return Some(Outcome::Quit),
+        };
+        shared.pending.fetch_sub(1, Ordering::SeqCst);
+        if let Control::Play = control { continue }
+        return Some(Outcome::Control(control));
+    }
+    None
+}
+
+// the stream is read ahead in its own thread, the decoder doesn't wait
+// for the network and a command stops a stalled read
+struct Prefetch {
+    chunks: Mutex<Receiver<Result<Vec<u8>>>>,
+    chunk: Vec<u8>,
+    offset: usize,
+    shared: Arc<Shared>,
+}
+
+impl Prefetch {
+    fn new(mut reader: Box<dyn Read + Send>, shared: Arc<Shared>) -> Self {
+        let (sender, receiver) = sync_channel(READ_AHEAD);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            match reader.read(&mut chunk) {
+                Ok(0) => return,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    // the decoder is gone
+                    if sender.send(Ok(chunk)).is_err() { return }
+                },
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                },
+            }
+        });
+        Prefetch { chunks: Mutex::new(receiver), chunk: Vec::new(), offset: 0, shared }
+    }
+}
+
+impl Read for Prefetch {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.offset >= self.chunk.len() {
+            let chunks = self.chunks.lock().expect("poisoned prefetch");
+            loop {
+                match chunks.recv_timeout(WAIT) {
+                    Ok(chunk) => {
+                        self.chunk = chunk?;
+                        self.offset = 0;
+                        break;
+                    },
+                    Err(RecvTimeoutError::Timeout) => {
+                        if self.shared.pending() {
+                            return Err(Error::other("interrupted by a command"));
+                        }
+                        self.shared.update(|s| if s.playback == Playback::Playing { s.playback = Playback::Buffering });
+                    },
+                    Err(RecvTimeoutError::Disconnected) => return Ok(0),
+                }
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.offset);
+        buf[..n].copy_from_slice(&self.chunk[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    const RATE: u32 = 8000;
+
+    // 16 bits mono
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let size = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + size).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        for field in [16u32, 1 | 1 << 16, RATE, RATE * 2, 2 | 16 << 16] {
+            wav.extend_from_slice(&field.to_le_bytes());
+        }
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&size.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+
+    fn tone(seconds: u32, level: i16) -> Vec<i16> {
+        (0..RATE * seconds).map(|i| if i % 20 < 10 { level } else { -level }).collect()
+    }
+
+    fn file(name: &str, samples: &[i16]) -> PathBuf {
+        let dir = std::env::temp_dir().join("clock-native-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, wav(samples)).unwrap();
+        path
+    }
+
+    // a radio: /ok plays a tone, /icy plays it with titles, /redirect goes to /ok, /stall never sends anything, others are missing
+    // /relative and /radio/relative redirect to /ok and /radio/live.wav, another tone, with relative locations
+    fn server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let location = base.clone();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let mut socket = socket.unwrap();
+                let location = location.clone();
+                thread::spawn(move || {
+                    // the whole request is read, hanging up early would break the player's writes
+                    let mut lines = BufReader::new(socket.try_clone().unwrap()).lines().map_while(|l| l.ok());
+                    let request = lines.next().unwrap_or_default();
+                    lines.take_while(|l| !l.is_empty()).for_each(drop);
+                    let answer = match request.split_whitespace().nth(1) {
+                        Some("/ok") | Some("/radio/live.wav") => {
+                            socket.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: audio/wav\r\n\r\n").unwrap();
+                            &wav(&tone(3, 4000))[..]
+                        },
+                        Some("/icy") => {
+                            socket.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: audio/wav\r\nicy-metaint: 1000\r\n\r\n").unwrap();
+                            let metadata = b"\x02StreamTitle='Tone';\0\0\0\0\0\0\0\0\0\0\0";
+                            for audio in wav(&tone(3, 4000)).chunks(1000) {
+                                if socket.write_all(audio).and_then(|_| socket.write_all(metadata)).is_err() { break }
+                            }
+                            &[][..]
+                        },
+                        Some("/redirect") => {
+                            write!(socket, "HTTP/1.0 302 Found\r\nLocation: {}/ok\r\n\r\n", location).unwrap();
+                            &[][..]
+                        },
+                        Some("/relative") => {
+                            socket.write_all(b"HTTP/1.0 302 Found\r\nLocation: /ok\r\n\r\n").unwrap();
+                            &[][..]
+                        },
+                        Some("/radio/relative") => {
+                            socket.write_all(b"HTTP/1.0 302 Found\r\nLocation: live.wav\r\n\r\n").unwrap();
+                            &[][..]
+                        },
+                        Some("/stall") => {
+                            socket.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\n\r\n").unwrap();
+                            thread::sleep(Duration::from_secs(10));
+                            &[][..]
+                        },
+                        _ => &b"HTTP/1.0 404 Not Found\r\n\r\n"[..],
+                    };
+                    // the player hangs up when it stops
+                    let _ = socket.write_all(answer);
+                });
+            }
+        });
+        base
+    }
+
+    // the state once the condition is met, or after a while
+    fn wait(native: &mut Native, until: impl Fn(&PlayerState) -> bool) -> PlayerState {
+        for _ in 0..50 {
+            let state = native.state().unwrap();
+            if until(&state) { return state }
+            thread::sleep(WAIT);
+        }
+        native.state().unwrap()
+    }
+
+    #[test]
+    fn silent_samples() {
+        assert!(is_silent(&[0, 12, -63, 63]));
+        assert!(!is_silent(&[0, -64]));
+        assert!(!is_silent(&[i16::MIN]));
+    }
+
+    #[test]
+    fn silence_heard() {
+        let quiet = file("quiet.wav", &tone(3, 20));
+        let loud = file("loud.wav", &tone(3, 4000));
+        let mut native = Native::new(NativeOutput::Null, "", "");
+        native.start().unwrap();
+        // muted, the sound is still heard
+        native.set_volume(0).unwrap();
+        native.load(&[loud.to_str().unwrap()], true).unwrap();
+        let state = wait(&mut native, |s| s.position >= Some(1));
+        assert_eq!((state.playback, state.silence), (Playback::Playing, Some(0)));
+        native.load(&[quiet.to_str().unwrap()], true).unwrap();
+        let state = wait(&mut native, |s| s.silence >= Some(1));
+        assert_eq!(state.silence, Some(1));
+        native.stop().unwrap();
+        assert_eq!(wait(&mut native, |s| s.playback == Playback::Stopped).silence, None);
+    }
+
+    #[test]
+    fn streams() {
+        let base = server();
+        let mut native = Native::new(NativeOutput::Null, "", "");
+        assert!(native.play().is_err());
+        assert!(native.start().unwrap());
+        assert!(!native.start().unwrap());
+        native.load(&[&format!("{}/ok", base)], true).unwrap();
+        let state = wait(&mut native, |s| s.position >= Some(1));
+        assert_eq!((state.playback, state.position), (Playback::Playing, Some(1)));
+        // each stream starts from a stopped player, not to see the position of the previous one
+        let stopped = |native: &mut Native| {
+            native.stop().unwrap();
+            wait(native, |s| s.position.is_none());
+        };
+        for redirect in ["redirect", "relative", "radio/relative"] {
+            stopped(&mut native);
+            native.load(&[&format!("{}/{}", base, redirect)], true).unwrap();
+            assert_eq!(wait(&mut native, |s| s.position.is_some()).playback, Playback::Playing);
+        }
+        stopped(&mut native);
+        // the next entry plays when one fails, an error when none plays
+        native.load(&[&format!("{}/missing", base), &format!("{}/ok", base)], true).unwrap();
+        assert_eq!(wait(&mut native, |s| s.position.is_some()).input, Some(format!("{}/ok", base)));
+        native.load(&[&format!("{}/missing", base), "missing.mp3"], true).unwrap();
+        assert_eq!(wait(&mut native, |s| s.playback == Playback::Error).playback, Playback::Error);
+        // a stalled stream doesn't keep commands from going through
+        native.load(&[&format!("{}/stall", base)], true).unwrap();
+        thread::sleep(WAIT * 10);
+        assert_eq!(native.state().unwrap().playback, Playback::Buffering);
+        native.stop().unwrap();
+        assert_eq!(wait(&mut native, |s| s.playback == Playback::Stopped).playback, Playback::Stopped);
+    }
+
+    #[test]
+    fn icy_titles() {
+        let base = server();
+        let mut native = Native::new(NativeOutput::Null, "", "");
+        native.start().unwrap();
+        native.load(&[&format!("{}/icy", base)], true).unwrap();
+        let state = wait(&mut native, |s| s.title.is_some() && s.position >= Some(1));
+        assert_eq!((state.playback, state.title), (Playback::Playing, Some("Tone".to_string())));
+        // the title belongs to the stream
+        native.stop().unwrap();
+        assert_eq!(wait(&mut native, |s| s.playback == Playback::Stopped).title, None);
+    }
+
+    // the volume is applied to the samples
+    #[test]
+    fn wav_output() {
+        let source = file("source.wav", &tone(1, 4000));
+        let output = std::env::temp_dir().join("clock-native-test").join("output.wav");
+        let mut native = Native::new(NativeOutput::Wav, "", output.to_str().unwrap());
+        native.start().unwrap();
+        native.set_volume(128).unwrap();
+        native.load(&[source.to_str().unwrap()], true).unwrap();
+        wait(&mut native, |s| s.position >= Some(1));
+        native.stop().unwrap();
+        wait(&mut native, |s| s.playback == Playback::Stopped);
+        // closed after a while without anything to play
+        let mut written = Vec::new();
+        for _ in 0..50 {
+            written = fs::read(&output).unwrap();
+            if written.len() > 44 && u32::from_le_bytes([written[40], written[41], written[42], written[43]]) > 0 { break }
+            thread::sleep(WAIT);
+        }
+        let samples: Vec<i16> = written[44..].chunks(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        assert!(samples.len() >= RATE as usize);
+        assert_eq!(samples.iter().map(|s| s.abs()).max(), Some(2000));
+    }
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::files::write_atomic;
+
+/* Alarm changes made with the buttons, and missed alarms, kept across restarts
+ *
+ * Like settings, they are saved in the state directory each time they change.
+ */
+
+const FILE: &str = "overrides.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Overrides {
+    // occurrence of an alarm that won't ring
+    pub skipped: Option<DateTime<Local>>,
+    // end of the nap
+    pub nap: Option<DateTime<Local>>,
+    // alarm missed while the clock was off, until dismissed
+    pub missed: Option<DateTime<Local>>,
+    // alarms turned off with the time button, missing from older files
+    #[serde(default)]
+    pub alarms_off: bool,
+}
+
+impl Overrides {
+    // nothing when there is nothing saved yet or the file is broken
+    pub fn load(state_dir: &str) -> Self {
+        let path = Path::new(state_dir).join(FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => return Overrides::default(),
+        };
+        match toml::from_str(&content) {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                println!("Invalid overrides {}: {}, ignored", path.display(), e);
+                Overrides::default()
+            },
+        }
+    }
+
+    pub fn save(&self, state_dir: &str) -> io::Result<()> {
+        let content = toml::to_string(self).map_err(io::Error::other)?;
+        write_atomic(&Path::new(state_dir).join(FILE), content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn saved() {
+        let dir = std::env::temp_dir().join("clock-overrides-test");
+        let dir = dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(dir);
+        assert_eq!(Overrides::load(dir), Overrides::default());
+        let overrides = Overrides {
+            skipped: Some(Local.with_ymd_and_hms(2026, 10, 20, 7, 0, 0).unwrap()),
+            nap: Some(Local.with_ymd_and_hms(2026, 10, 19, 14, 20, 0).unwrap()),
+            missed: None,
+            alarms_off: true,
+        };
+        overrides.save(dir).unwrap();
+        assert_eq!(Overrides::load(dir), overrides);
+        // before alarms_off
+        fs::write(Path::new(dir).join(FILE), "nap = \"2026-10-19T14:20:00+02:00\"\n").unwrap();
+        assert!(Overrides::load(dir).nap.is_some());
+        fs::write(Path::new(dir).join(FILE), "nap = 12\n").unwrap();
+        assert_eq!(Overrides::load(dir), Overrides::default());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
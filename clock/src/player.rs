@@ -1,46 +1,238 @@
-use std::process::*;
-use std::time::*;
+use serde::{Deserialize, Serialize};
 use std::io::Result;
-use std::io::Write;
-use std::thread::sleep;
+use std::path::Path;
 
+use crate::config::PlayerConfig;
+use crate::settings::{MAX_VOLUME, VOLUME_STEP};
+use crate::vlc::Vlc;
+use crate::mpv::Mpv;
+use crate::mpd::Mpd;
+use crate::mock::Mock;
+#[cfg(feature = "native")]
+use crate::native::Native;
+use crate::stations::Station;
+use crate::library::Library;
 
-const FALLBACK: &str = "fallback.mp3";
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Playback {
+    #[default]
+    Stopped,
+    // opening or buffering the stream
+    Buffering,
+    Playing,
+    Paused,
+    // the stream failed
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct PlayerState {
+    pub playback: Playback,
+    pub volume: Option<u16>,
+    pub input: Option<String>,
+    pub title: Option<String>,
+    // seconds played, to see a stalled stream
+    pub position: Option<u64>,
+    // seconds of silence in a row, only the native player hears it
+    pub silence: Option<u64>,
+}
 
-static urls: [&str; 1] = [
-    "http://direct.franceinter.fr/live/franceinter-hifi.aac"
-];
+// what plays the sound: vlc, mpv, mpd or nothing
+// volumes are vlc volumes, 256 is 100%
+pub trait Backend: Send {
+    // start the player when it is not running, true when it must be set up again
+    fn start(&mut self) -> Result<bool>;
+    // replace the playlist, looping on it, and play it or not
+    fn load(&mut self, urls: &[&str], play: bool) -> Result<()>;
+    fn play(&mut self) -> Result<()>;
+    fn stop(&mut self) -> Result<()>;
+    fn set_volume(&mut self, volume: u16) -> Result<()>;
+    // stopped when the player is not running
+    fn state(&mut self) -> Result<PlayerState>;
+}
+
+pub fn open_backend(config: &PlayerConfig) -> Box<dyn Backend> {
+    match config {
+        PlayerConfig::Vlc => Box::new(Vlc::new()),
+        PlayerConfig::Mpv { socket } => Box::new(Mpv::new(socket)),
+        PlayerConfig::Mpd { address } => Box::new(Mpd::new(address)),
+        #[cfg(feature = "native")]
+        PlayerConfig::Native { output, device, path } => Box::new(Native::new(*output, device, path)),
+        #[cfg(not(feature = "native"))]
+        PlayerConfig::Native { .. } => {
+            println!("The clock is built without the native player, using vlc");
+            Box::new(Vlc::new())
+        },
+        PlayerConfig::Mock { script } => Box::new(Mock::new(script)),
+    }
+}
 
 
 pub struct Player {
     // show must go on, so if there is a problem we still have 
-    // a player structure without the process
-    process: Option<Child>,
+    // a player structure, the backend starts its process when needed
+    backend: Box<dyn Backend>,
+    started: bool,
+    stations: Vec<Station>,
     current: usize,
+    playing: bool,
+    // volume set when the player starts
+    default_volume: u16,
+    // current player volume
+    volume: u16,
+    // played when the stations don't
+    fallback_file: String,
+    // the fallback file replaced the stations, until the next stop
+    fallback: bool,
+    // playing without sound before an alarm, until play
+    muted: bool,
+    // the recorded station replaced the live one, until the next stop
+    recorded: bool,
+    // stations that are directories of music
+    library: Library,
 }
 
 impl Player {
-    pub fn new() -> Self {
-        Player { process: None, current: 0 }
+    // stations is never empty
+    pub fn new(backend: Box<dyn Backend>, stations: Vec<Station>, fallback_file: &str, library: Library) -> Self {
+        Player { backend, started: false, stations, current: 0, playing: false, default_volume: 256, volume: 256,
+                 fallback_file: fallback_file.to_string(), fallback: false, muted: false, recorded: false, library }
     }
 
-    pub fn init(&mut self) -> Result<()> {
-        self.process = Some(Player::spawn()?);
-        self.send_command("volume 256")?;
-        self.setup()?;
-        Ok(())
+    pub fn station(&self) -> &Station {
+        &self.stations[self.current]
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn station_count(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn stations(&self) -> &[Station] {
+        &self.stations
+    }
+
+    // once playlist urls are resolved, the station playing now keeps its url until it changes
+    pub fn set_stations(&mut self, stations: Vec<Station>) {
+        if stations.is_empty() { return }
+        if self.current >= stations.len() {
+            self.current = 0;
+        }
+        self.stations = stations;
+    }
+
+    // it should be playing, whatever the stream does
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    pub fn is_recorded(&self) -> bool {
+        self.recorded
+    }
+
+    pub fn is_warm(&self) -> bool {
+        self.muted
+    }
+
+    // urls are trusted, files must be there
+    pub fn fallback_ok(&self) -> bool {
+        self.fallback_file.contains("://") || Path::new(&self.fallback_file).is_file()
+    }
+
+    // play muted so the sound is there at once when the alarm rings
+    pub fn warm_up(&mut self, station: Option<usize>) -> Result<()> {
+        self.alive()?;
+        if let Some(station) = station.filter(|s| *s < self.stations.len()) {
+            self.current = station;
+        }
+        self.fallback = false;
+        self.muted = true;
+        self.playing = true;
+        self.requeue(true)
+    }
+
+    // the station recorded before, the live station then the fallback file when it fails
+    pub fn play_recording(&mut self, url: &str, station: Option<usize>) -> Result<()> {
+        self.alive()?;
+        if let Some(station) = station.filter(|s| *s < self.stations.len()) {
+            self.current = station;
+        }
+        self.fallback = false;
+        self.recorded = true;
+        self.playing = true;
+        let mut urls = vec![url.to_string()];
+        urls.append(&mut self.playlist());
+        // the volume first, the sound must not come out at the previous one
+        self.backend.set_volume(self.output_volume())?;
+        self.backend.load(&urls.iter().map(|u| u.as_str()).collect::<Vec<_>>(), true)
+    }
+
+    // what the player tells it plays, remembered for music libraries
+    pub fn played(&mut self, input: &str) {
+        if self.fallback || self.recorded || !Library::is_library(&self.station().url) { return }
+        self.library.played(input);
+    }
+
+    pub fn set_default_volume(&mut self, volume: u16) {
+        self.default_volume = volume;
+    }
+
+    pub fn volume(&self) -> u16 {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: u16) -> Result<()> {
+        self.alive()?;
+        self.volume = volume.min(MAX_VOLUME);
+        self.backend.set_volume(self.output_volume())
+    }
+
+    // when the radio doesn't wake us up
+    pub fn play_fallback(&mut self) -> Result<()> {
+        self.alive()?;
+        self.fallback = true;
+        self.recorded = false;
+        self.playing = true;
+        self.backend.set_volume(self.output_volume())?;
+        self.backend.load(&[&self.fallback_file], true)
+    }
+
+    // select a station by its index, without playing it
+    pub fn select(&mut self, station: usize) -> Result<()> {
+        self.alive()?;
+        if station >= self.stations.len() || station == self.current { return Ok(()) }
+        self.current = station;
+        self.requeue(false)
+    }
+
+    // play a station now, leaving the fallback file
+    pub fn play_station(&mut self, station: usize) -> Result<()> {
+        self.alive()?;
+        self.current = station % self.stations.len();
+        self.fallback = false;
+        self.playing = true;
+        self.requeue(true)
     }
 
     pub fn change_url(&mut self, next: bool) -> Result<()> {
         self.alive()?;
+        self.muted = false;
         if next {
             self.current += 1; 
-            if self.current >= urls.len() { 
+            if self.current >= self.stations.len() { 
                 self.current = 0;
             }
         } else {
             if self.current == 0 {
-                self.current = urls.len()-1;
+                self.current = self.stations.len()-1;
             } else {
                 self.current -= 1;
             }
@@ -50,71 +242,222 @@ impl Player {
 
     pub fn play(&mut self) -> Result<()> {
         self.alive()?;
-        self.send_command("play")
+        self.playing = true;
+        self.backend.play()?;
+        if self.muted {
+            self.muted = false;
+            self.backend.set_volume(self.output_volume())?;
+        }
+        Ok(())
+    }
+
+    // the alarm rings whatever happened before, the fallback file replaces a source that failed
+    pub fn ring(&mut self, source: Result<()>, volume: Option<u16>) -> Result<()> {
+        if let Err(e) = source {
+            println!("Cannot play the alarm source {:?}, playing the fallback file", e);
+            if let Err(e) = self.play_fallback() {
+                println!("Cannot play the fallback file {:?}", e);
+            }
+        }
+        let volume = volume.map_or(Ok(()), |v| self.set_volume(v));
+        self.play().and(volume)
     }
 
     pub fn stop(&mut self) -> Result<()> {
         self.alive()?;
-        self.send_command("stop")
+        self.playing = false;
+        self.muted = false;
+        self.backend.stop()?;
+        // live stations are back for the next play
+        if self.fallback || self.recorded {
+            self.fallback = false;
+            self.requeue(false)?;
+        }
+        Ok(())
+    }
+
+    pub fn toggle(&mut self) -> Result<()> {
+        if self.playing && !self.muted {
+            self.stop()
+        } else {
+            self.play()
+        }
     }
 
     pub fn voldown(&mut self) -> Result<()> {
         self.alive()?;
-        self.send_command("voldown")
+        self.set_volume(self.volume.saturating_sub(VOLUME_STEP))
     }
 
     pub fn volup(&mut self) -> Result<()> {
         self.alive()?;
-        self.send_command("volup")
+        self.set_volume(self.volume + VOLUME_STEP)
+    }
+
+    pub fn state(&mut self) -> Result<PlayerState> {
+        self.backend.state()
     }
 
+    // a new player process gets the volume and the playlist
     fn alive(&mut self) -> Result<()> {
-        match self.process {
-            None => self.init(),
-            Some(ref mut p) => match p.try_wait()? {
-                Some(_) => self.respawn(),
-                None => Ok(()),
-            }
+        if !self.backend.start()? { return Ok(()) }
+        if !self.started {
+            self.started = true;
+            self.volume = self.default_volume;
         }
+        self.fallback = false;
+        self.requeue(false)
     }
 
-    fn respawn(&mut self) -> Result<()> {
-        self.process = Some(Player::spawn()?);
-        self.setup()
+    // the station volume offset changes with the station,
+    // it is set before the station plays so a warm up is muted from the start
+    fn requeue(&mut self, play: bool) -> Result<()> {
+        self.recorded = false;
+        let urls = self.playlist();
+        self.backend.set_volume(self.output_volume())?;
+        self.backend.load(&urls.iter().map(|u| u.as_str()).collect::<Vec<_>>(), play)
     }
 
-    fn requeue(&mut self, play: bool) -> Result<()> {
-        self.send_command("clear")?;
-        let action = if play { "add" } else { "enqueue" };
-        let cmd = format!("{} {}", action, urls[self.current]);
-        self.send_command(&cmd)?;
-        let cmd = format!("enqueue {}", FALLBACK);
-        self.send_command(&cmd)
-    }
-
-    fn setup(&mut self) -> Result<()> {
-        sleep(Duration::from_millis(300));
-        self.requeue(false)?;
-        self.send_command("loop on")
-    }
-
-    fn send_command(&mut self, command: &str) -> Result<()> {
-        let process = match self.process {
-            Some(ref mut p) => p,
-            None => return Err(std::io::Error::last_os_error()),
-        };
-        let stdin = process.stdin.as_mut().expect("Failed to open stdin");
-        let cmd = format!("{}\n", command);
-        println!("Sending {}", command);
-        stdin.write_all(cmd.as_bytes())?;
-        Ok(())
+    // the station, or the shuffled tracks of a music directory, then the fallback file
+    fn playlist(&self) -> Vec<String> {
+        let url = &self.station().url;
+        let mut urls = if Library::is_library(url) { self.library.playlist(url) } else { vec![url.clone()] };
+        urls.push(self.fallback_file.clone());
+        urls
+    }
+
+    // the fallback file has no offset
+    fn output_volume(&self) -> u16 {
+        if self.muted { return 0 }
+        let offset = if self.fallback { 0 } else { self.station().volume_offset };
+        (self.volume as i32 + offset as i32).clamp(0, MAX_VOLUME as i32) as u16
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(name: &str, volume_offset: i16) -> Station {
+        Station { name: name.to_string(), url: format!("http://radio/{}", name), label: name.to_uppercase(), volume_offset }
+    }
+
+    fn player(script: &[Playback]) -> Player {
+        let library = Library::load(std::env::temp_dir().join("clock-player-test").to_str().unwrap());
+        Player::new(Box::new(Mock::new(script)), vec![station("fip", 0), station("inter", 32)], "fallback.mp3", library)
+    }
+
+    #[test]
+    fn warm_up_then_ring() {
+        let mut player = player(&[]);
+        player.set_default_volume(128);
+        player.warm_up(Some(1)).unwrap();
+        assert!(player.is_warm() && player.is_playing());
+        let state = player.state().unwrap();
+        assert_eq!((state.playback, state.volume, state.input.as_deref()), (Playback::Playing, Some(0), Some("http://radio/inter")));
+        // the station offset is added
+        player.play().unwrap();
+        assert!(!player.is_warm());
+        assert_eq!(player.state().unwrap().volume, Some(160));
+        player.stop().unwrap();
+        assert_eq!(player.state().unwrap().playback, Playback::Stopped);
     }
 
-    fn spawn() -> Result<Child> {
-        Command::new("vlc")
-                .arg("-Irc")
-                .stdin(Stdio::piped())
-                .spawn()
+    #[test]
+    fn muted_before_playing() {
+        let mut mock = Mock::new(&[]);
+        let commands = mock.commands();
+        let library = Library::load(std::env::temp_dir().join("clock-player-test").to_str().unwrap());
+        let mut player = Player::new(Box::new(mock), vec![station("fip", 0), station("inter", 32)], "fallback.mp3", library);
+        player.set_default_volume(128);
+        player.warm_up(Some(1)).unwrap();
+        player.play_recording("http://127.0.0.1:8731/1773122400", None).unwrap();
+        assert_eq!(*commands.lock().unwrap(), vec![
+            "start",
+            "volume 128",
+            "load [\"http://radio/fip\", \"fallback.mp3\"] stopped",
+            "volume 0",
+            "load [\"http://radio/inter\", \"fallback.mp3\"] playing",
+            "volume 0",
+            "load [\"http://127.0.0.1:8731/1773122400\", \"http://radio/inter\", \"fallback.mp3\"] playing",
+        ]);
     }
 
+    #[test]
+    fn ring_after_failed_source() {
+        let mut player = player(&[Playback::Error]);
+        player.warm_up(Some(0)).unwrap();
+        assert_eq!(player.state().unwrap().playback, Playback::Error);
+        let source = player.select(1);
+        assert!(source.is_err());
+        player.ring(source, Some(200)).unwrap();
+        assert!(player.is_fallback() && !player.is_warm());
+        let state = player.state().unwrap();
+        assert_eq!((state.playback, state.volume, state.input.as_deref()), (Playback::Playing, Some(200), Some("fallback.mp3")));
+    }
+
+    #[test]
+    fn ring_whatever_failed() {
+        // the fallback fails too, it rings anyway
+        let mut player = player(&[Playback::Error, Playback::Stopped, Playback::Error]);
+        player.warm_up(None).unwrap();
+        player.state().unwrap();
+        let source = player.select(1);
+        player.state().unwrap();
+        player.state().unwrap();
+        assert!(player.ring(source, None).is_ok());
+        assert!(!player.is_warm());
+        assert_eq!(player.state().unwrap().playback, Playback::Playing);
+    }
+
+    #[test]
+    fn warm_up_failed() {
+        let mut player = player(&[Playback::Error]);
+        player.set_default_volume(100);
+        player.warm_up(None).unwrap();
+        player.state().unwrap();
+        // the next command fails, the station stays quiet
+        assert!(player.warm_up(Some(1)).is_err());
+        assert_eq!(player.state().unwrap().volume, Some(0));
+        player.ring(Ok(()), None).unwrap();
+        assert_eq!(player.state().unwrap().volume, Some(132));
+    }
+
+    #[test]
+    fn fallback_until_stop() {
+        let mut player = player(&[]);
+        player.play_fallback().unwrap();
+        assert!(player.is_fallback());
+        assert_eq!(player.state().unwrap().input.as_deref(), Some("fallback.mp3"));
+        player.stop().unwrap();
+        assert!(!player.is_fallback());
+        assert_eq!(player.state().unwrap().input.as_deref(), Some("http://radio/fip"));
+        player.change_url(false).unwrap();
+        assert_eq!(player.station().label, "INTER");
+        assert!(!player.fallback_ok());
+    }
+
+    #[test]
+    fn resolved_stations() {
+        let mut player = player(&[]);
+        player.change_url(true).unwrap();
+        player.set_stations(vec![station("fip", 0), station("inter", 0), station("jazz", 0)]);
+        assert_eq!(player.station().name, "inter");
+        player.set_stations(vec![station("fip", 0)]);
+        assert_eq!(player.current(), 0);
+        player.set_stations(Vec::new());
+        assert_eq!(player.station_count(), 1);
+    }
+
+    #[test]
+    fn scripted_failure() {
+        let mut player = player(&[Playback::Buffering, Playback::Error]);
+        player.play().unwrap();
+        assert_eq!(player.state().unwrap().playback, Playback::Buffering);
+        assert_eq!(player.state().unwrap().playback, Playback::Error);
+        assert!(player.stop().is_err());
+        player.play().unwrap();
+        assert_eq!(player.state().unwrap().playback, Playback::Playing);
+    }
 }
@@ -0,0 +1,134 @@
+use rppal::gpio::*;
+use std::io;
+use std::sync::mpsc::{channel, Receiver};
+
+use crate::input::*;
+use crate::keys::Button;
+
+/* Quadrature rotary encoder
+ *
+ *  A  ‾‾|__|‾‾|__|‾‾     A and B are out of phase by a quarter period,
+ *  B  ‾|__|‾‾|__|‾‾‾     the one that changes first gives the direction.
+ *
+ * Both pins use the internal pull-up and the common pin goes to ground.
+ * Each rotation by one detent produces one button event.
+ */
+
+// movement for a transition from state (old A, old B, new A, new B), 0 for invalid or no move
+#[rustfmt::skip]
+const TRANSITIONS: [i8; 16] = [
+     0, -1,  1,  0,
+     1,  0,  0, -1,
+    -1,  0,  0,  1,
+     0,  1, -1,  0,
+];
+
+pub struct Rotary {
+    // pins must be kept alive for their interrupts to fire
+    _pins: Vec<InputPin>,
+    events: Receiver<(usize, Level)>,
+    levels: [Level; 2],
+    position: i8,
+    steps: i8,
+    clockwise: Button,
+    counter_clockwise: Button,
+}
+
+impl Rotary {
+    pub fn new(gpio: &Gpio, pin_a: u8, pin_b: u8, clockwise: Button, counter_clockwise: Button, steps: i8)
+               -> Result<Self> {
+        let (tx, events) = channel();
+        let mut pins = [gpio.get(pin_a)?.into_input_pullup(), gpio.get(pin_b)?.into_input_pullup()];
+        let levels = [pins[0].read(), pins[1].read()];
+        for (index, pin) in pins.iter_mut().enumerate() {
+            let tx = tx.clone();
+            // same trick as GpioButtons, synchronous interrupts are taken by Keys
+            pin.set_async_interrupt(Trigger::Both, None, move |event| {
+                let level = if event.trigger == Trigger::RisingEdge { Level::High } else { Level::Low };
+                let _ = tx.send((index, level));
+            })?;
+        }
+        Ok(Rotary {
+            _pins: pins.into(),
+            events,
+            levels,
+            position: 0,
+            steps: steps.max(1),
+            clockwise,
+            counter_clockwise,
+        })
+    }
+
+    fn state(&self) -> usize {
+        let a = if self.levels[0] == Level::High { 2 } else { 0 };
+        let b = if self.levels[1] == Level::High { 1 } else { 0 };
+        a + b
+    }
+}
+
+impl Input for Rotary {
+    fn poll_press(&mut self) -> io::Result<Option<Press>> {
+        let (index, level) = self.events.recv().map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let old = self.state();
+        self.levels[index] = level;
+        self.position += TRANSITIONS[old * 4 + self.state()];
+        if self.position >= self.steps {
+            self.position = 0;
+            Ok(Some(Press { button: self.clockwise, gesture: Gesture::Short }))
+        } else if self.position <= -self.steps {
+            self.position = 0;
+            Ok(Some(Press { button: self.counter_clockwise, gesture: Gesture::Short }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // states are A * 2 + B
+    fn moves(states: &[usize]) -> i8 {
+        states.windows(2).map(|w| TRANSITIONS[w[0] * 4 + w[1]]).sum()
+    }
+
+    #[test]
+    fn transitions() {
+        // one detent each way
+        assert_eq!(moves(&[3, 1, 0, 2, 3]), 4);
+        assert_eq!(moves(&[3, 2, 0, 1, 3]), -4);
+        // bounce on one pin
+        assert_eq!(moves(&[3, 1, 3, 1, 3]), 0);
+        // both pins at once is invalid
+        assert_eq!(moves(&[3, 0]), 0);
+    }
+
+    #[test]
+    fn poll_press() {
+        let (tx, events) = channel();
+        let mut rotary = Rotary {
+            _pins: Vec::new(),
+            events,
+            levels: [Level::High, Level::High],
+            position: 0,
+            steps: 4,
+            clockwise: Button::Right,
+            counter_clockwise: Button::Left,
+        };
+        let mut presses = Vec::new();
+        let mut turn = |edges: &[(usize, Level)]| {
+            for e in edges {
+                tx.send(*e).unwrap();
+                presses.push(rotary.poll_press().unwrap().map(|p| p.button));
+            }
+        };
+        // one detent clockwise, A falls first
+        turn(&[(0, Level::Low), (1, Level::Low), (0, Level::High), (1, Level::High)]);
+        // a bounce on B doesn't move
+        turn(&[(1, Level::Low), (1, Level::High)]);
+        // one detent counter clockwise
+        turn(&[(1, Level::Low), (0, Level::Low), (1, Level::High), (0, Level::High)]);
+        assert_eq!(presses, vec![None, None, None, Some(Button::Right), None, None, None, None, None, Some(Button::Left)]);
+    }
+}
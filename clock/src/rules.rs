@@ -0,0 +1,267 @@
+use chrono::*;
+
+use crate::alarm::local;
+use crate::config::{AllDay, RulesConfig};
+use crate::holidays::DaysOff;
+use crate::ical::Event;
+
+/* Rules turning the events of a day into a wake up time
+ *
+ *   - events in an ignored category (or with #category in their summary) don't exist
+ *   - all-day events are ignored or cancel the alarm of their day
+ *   - wake up = first event - preparation - commute to its location
+ *   - never earlier than earliest, unless the event starts before it
+ *   - never later than latest: events waking up later are not morning events, a dinner doesn't wake up
+ *   - a day without event uses the default alarm of its weekday, if any, unless it's a day off
+ *
+ * Every decision comes with its reason, to understand why the clock rang (or didn't).
+ */
+
+pub struct Decision {
+    pub day: NaiveDate,
+    pub wake_up: Option<DateTime<Local>>,
+    pub reason: String,
+}
+
+pub struct Commute {
+    location: String,
+    duration: Duration,
+}
+
+pub struct Rules {
+    preparation: Duration,
+    ignore: Vec<String>,
+    all_day: AllDay,
+    commutes: Vec<Commute>,
+    earliest: Option<NaiveTime>,
+    latest: Option<NaiveTime>,
+    default: Option<(NaiveTime, Vec<Weekday>)>,
+    days_off: DaysOff,
+}
+
+fn parse_time(time: &Option<String>) -> Option<NaiveTime> {
+    let time = time.as_ref()?;
+    match NaiveTime::parse_from_str(time, "%H:%M") {
+        Ok(t) => Some(t),
+        Err(e) => { println!("Invalid agenda rule time {}: {}", time, e); None },
+    }
+}
+
+impl Rules {
+    pub fn new(config: &RulesConfig, days_off: DaysOff, preparation: Duration) -> Self {
+        Rules {
+            preparation,
+            ignore: config.ignore.iter().map(|c| c.to_lowercase()).collect(),
+            all_day: config.all_day,
+            commutes: config.commute.iter().map(|c| Commute {
+                location: c.location.to_lowercase(),
+                duration: Duration::minutes(c.minutes),
+            }).collect(),
+            earliest: parse_time(&config.earliest),
+            latest: parse_time(&config.latest),
+            default: config.default.as_ref().and_then(|d| {
+                Some((parse_time(&Some(d.time.clone()))?, d.weekdays.clone()))
+            }),
+            days_off,
+        }
+    }
+
+    fn ignored(&self, event: &Event) -> bool {
+        let summary = event.summary.to_lowercase();
+        self.ignore.iter().any(|tag| {
+            event.categories.iter().any(|c| c.to_lowercase() == *tag)
+                || summary.contains(&format!("#{}", tag))
+        })
+    }
+
+    // first matching location, case insensitive
+    fn commute(&self, location: &str) -> Option<&Commute> {
+        let location = location.to_lowercase();
+        self.commutes.iter().find(|c| location.contains(&c.location))
+    }
+
+    // wake up time for an event, and why
+    fn wake_up(&self, event: &Event) -> (DateTime<Local>, String) {
+        let mut reason = format!("first event {} at {}, {} min preparation",
+                                 event.summary, event.start.format("%H:%M"), self.preparation.num_minutes());
+        let mut wake_up = event.start - self.preparation;
+        if let Some(commute) = self.commute(&event.location) {
+            wake_up -= commute.duration;
+            reason += &format!(" + {} min to {}", commute.duration.num_minutes(), event.location);
+        }
+        (wake_up, reason)
+    }
+
+    pub fn decide(&self, events: &[Event], day: NaiveDate) -> Decision {
+        let events: Vec<&Event> = events.iter()
+            .filter(|e| e.start.date_naive() == day && !self.ignored(e))
+            .collect();
+        let decision = |wake_up, reason| Decision { day, wake_up, reason };
+
+        if self.all_day == AllDay::Skip {
+            if let Some(event) = events.iter().find(|e| e.all_day) {
+                return decision(None, format!("all-day event {}", event.summary));
+            }
+        }
+
+        let earliest = self.earliest.and_then(|t| local(day.and_time(t)));
+        let latest = self.latest.and_then(|t| local(day.and_time(t)));
+        let timed: Vec<&Event> = events.into_iter().filter(|e| !e.all_day).collect();
+        let morning: Vec<&Event> = timed.iter().copied()
+            .filter(|e| latest.is_none_or(|l| self.wake_up(e).0 <= l))
+            .collect();
+        let no_event = if morning.len() < timed.len() { "no morning event" } else { "no event" };
+
+        let (wake_up, start, mut reason) = match morning.iter().min_by_key(|e| e.start) {
+            Some(event) => {
+                let (wake_up, reason) = self.wake_up(event);
+                (wake_up, Some(event.start), reason)
+            },
+            None => match self.default {
+                Some((_, ref weekdays)) if weekdays.contains(&day.weekday()) && self.days_off.is_off(day) => {
+                    return decision(None, format!("{}, {}", no_event, self.days_off.reason(day).unwrap_or_default()));
+                },
+                Some((time, ref weekdays)) if weekdays.contains(&day.weekday()) => {
+                    match local(day.and_time(time)) {
+                        Some(wake_up) => (wake_up, None, format!("{}, default alarm", no_event)),
+                        None => return decision(None, format!("{}, invalid default time", no_event)),
+                    }
+                },
+                _ => return decision(None, no_event.to_string()),
+            },
+        };
+
+        // an event before earliest keeps its wake up time, being late is worse
+        let wake_up = match (earliest, latest) {
+            (Some(earliest), _) if wake_up < earliest && start.is_none_or(|s| earliest <= s) => {
+                reason += &format!(", moved to earliest {}", earliest.format("%H:%M"));
+                earliest
+            },
+            (_, Some(latest)) if wake_up > latest => {
+                reason += &format!(", moved to latest {}", latest.format("%H:%M"));
+                latest
+            },
+            _ => wake_up,
+        };
+        decision(Some(wake_up), reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RulesConfig;
+
+    fn day() -> NaiveDate {
+        // a Tuesday
+        NaiveDate::from_ymd_opt(2026, 3, 10).unwrap()
+    }
+
+    fn at(day: NaiveDate, hour: u32, min: u32) -> DateTime<Local> {
+        local(day.and_hms_opt(hour, min, 0).unwrap()).unwrap()
+    }
+
+    fn event(summary: &str, hour: u32, min: u32) -> Event {
+        let start = at(day(), hour, min);
+        Event {
+            uid: summary.to_string(),
+            summary: summary.to_string(),
+            location: String::new(),
+            categories: Vec::new(),
+            start,
+            end: start + Duration::hours(1),
+            all_day: false,
+        }
+    }
+
+    fn rules(config: &str) -> Rules {
+        let config: RulesConfig = toml::from_str(config).unwrap();
+        Rules::new(&config, DaysOff::default(), Duration::minutes(60))
+    }
+
+    fn wake_up(rules: &Rules, events: &[Event]) -> Option<(u32, u32)> {
+        rules.decide(events, day()).wake_up.map(|t| (t.hour(), t.minute()))
+    }
+
+    #[test]
+    fn first_event() {
+        let rules = rules("");
+        assert_eq!(wake_up(&rules, &[event("b", 10, 0), event("a", 8, 30)]), Some((7, 30)));
+        assert_eq!(rules.decide(&[event("a", 8, 30)], day()).reason, "first event a at 08:30, 60 min preparation");
+        // events of other days don't count
+        let tomorrow = Event { start: at(day().succ_opt().unwrap(), 8, 0), ..event("a", 8, 0) };
+        assert_eq!(wake_up(&rules, &[tomorrow]), None);
+    }
+
+    #[test]
+    fn ignored() {
+        let rules = rules("");
+        let category = Event { categories: vec!["No-Wake".to_string()], ..event("a", 8, 0) };
+        let tagged = event("call #no-wake", 8, 0);
+        assert_eq!(wake_up(&rules, &[category, tagged, event("b", 9, 0)]), Some((8, 0)));
+    }
+
+    #[test]
+    fn all_day() {
+        let holiday = Event { all_day: true, ..event("holiday", 0, 0) };
+        assert_eq!(wake_up(&rules(""), &[holiday.clone(), event("a", 9, 0)]), Some((8, 0)));
+        let rules = rules(r#"all_day = "skip""#);
+        let decision = rules.decide(&[holiday, event("a", 9, 0)], day());
+        assert_eq!((decision.wake_up, decision.reason.as_str()), (None, "all-day event holiday"));
+    }
+
+    #[test]
+    fn commute() {
+        let rules = rules(r#"
+            [[commute]]
+            location = "Office"
+            minutes = 45
+        "#);
+        let office = Event { location: "Main OFFICE, floor 2".to_string(), ..event("a", 9, 0) };
+        assert_eq!(wake_up(&rules, &[office]), Some((7, 15)));
+        let home = Event { location: "home".to_string(), ..event("a", 9, 0) };
+        assert_eq!(wake_up(&rules, &[home]), Some((8, 0)));
+    }
+
+    #[test]
+    fn bounds() {
+        let rules = rules(r#"
+            earliest = "06:00"
+            latest = "08:00"
+        "#);
+        assert_eq!(wake_up(&rules, &[event("a", 6, 30)]), Some((6, 0)));
+        assert_eq!(wake_up(&rules, &[event("a", 8, 45)]), Some((7, 45)));
+        // never after the event
+        assert_eq!(wake_up(&rules, &[event("a", 5, 30)]), Some((4, 30)));
+        assert_eq!(wake_up(&rules, &[event("a", 0, 30)]), Some((23, 30)));
+        // a dinner is not a morning event
+        let decision = rules.decide(&[event("dinner", 20, 0)], day());
+        assert_eq!((decision.wake_up, decision.reason.as_str()), (None, "no morning event"));
+        assert_eq!(wake_up(&rules, &[event("dinner", 20, 0), event("a", 7, 0)]), Some((6, 0)));
+    }
+
+    #[test]
+    fn default_alarm() {
+        let rules = rules(r#"
+            latest = "07:00"
+            [default]
+            time = "07:30"
+            weekdays = ["Tue", "Wed"]
+        "#);
+        assert_eq!(rules.decide(&[], day()).reason, "no event, default alarm, moved to latest 07:00");
+        assert_eq!(wake_up(&rules, &[event("dinner", 20, 0)]), Some((7, 0)));
+        assert_eq!(wake_up(&rules, &[event("a", 7, 30)]), Some((6, 30)));
+        // other weekdays
+        assert_eq!(rules.decide(&[], day().pred_opt().unwrap()).wake_up, None);
+        // a public holiday
+        let armistice = NaiveDate::from_ymd_opt(2026, 11, 11).unwrap();
+        let decision = rules.decide(&[], armistice);
+        assert_eq!((decision.wake_up, decision.reason.as_str()), (None, "no event, holiday Armistice Day"));
+    }
+
+    #[test]
+    fn no_event() {
+        let decision = rules("").decide(&[], day());
+        assert_eq!((decision.wake_up, decision.reason.as_str()), (None, "no event"));
+    }
+}
@@ -0,0 +1,92 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::Deserialize;
+
+/* When an alarm repeats, all rules are evaluated on local dates
+ *
+ *   repeat = "daily"
+ *   repeat = { weekdays = ["Mon", "Tue", "Wed", "Thu", "Fri"] }
+ *   repeat = { dates = ["2026-12-24", "2026-12-31"] }
+ *   repeat = { once = "2026-11-02" }
+ *   repeat = { every = { start = "2026-01-05", days = 14 } }
+ */
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Recurrence {
+    #[default]
+    Daily,
+    Weekdays(Vec<Weekday>),
+    Dates(Vec<NaiveDate>),
+    Once(NaiveDate),
+    Every { start: NaiveDate, days: u32 },
+}
+
+impl Recurrence {
+    // first date on or after a date, without looping over days when we can avoid it
+    pub fn next_date(&self, from: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Recurrence::Daily => Some(from),
+            Recurrence::Weekdays(days) => {
+                if days.is_empty() { return None }
+                let offset = (0..7).find(|d| days.contains(&(from + Duration::days(*d)).weekday()))?;
+                Some(from + Duration::days(offset))
+            },
+            Recurrence::Dates(dates) => dates.iter().filter(|d| **d >= from).min().copied(),
+            Recurrence::Once(day) => if *day >= from { Some(*day) } else { None },
+            Recurrence::Every { start, days } => {
+                if *days == 0 { return None }
+                if from <= *start { return Some(*start) }
+                let elapsed = (from - *start).num_days();
+                let period = *days as i64;
+                let periods = (elapsed + period - 1) / period;
+                Some(*start + Duration::days(periods * period))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    // day by day, the slow way
+    fn occurs(recurrence: &Recurrence, day: NaiveDate) -> bool {
+        match recurrence {
+            Recurrence::Daily => true,
+            Recurrence::Weekdays(days) => days.contains(&day.weekday()),
+            Recurrence::Dates(dates) => dates.contains(&day),
+            Recurrence::Once(once) => *once == day,
+            Recurrence::Every { start, days } => {
+                let elapsed = (day - *start).num_days();
+                *days > 0 && elapsed >= 0 && elapsed % *days as i64 == 0
+            },
+        }
+    }
+
+    #[test]
+    fn whole_years() {
+        let recurrences = vec![
+            Recurrence::Daily,
+            Recurrence::Weekdays(vec![Weekday::Mon, Weekday::Sat]),
+            Recurrence::Weekdays(vec![]),
+            Recurrence::Dates(vec![date(2027, 2, 28), date(2026, 5, 1)]),
+            Recurrence::Once(date(2026, 7, 14)),
+            Recurrence::Every { start: date(2026, 3, 3), days: 3 },
+            Recurrence::Every { start: date(2026, 3, 3), days: 14 },
+            Recurrence::Every { start: date(2026, 3, 3), days: 0 },
+        ];
+        for recurrence in &recurrences {
+            let mut day = date(2025, 12, 1);
+            // over a leap day
+            while day < date(2028, 3, 2) {
+                let expected = (0..1000).map(|d| day + Duration::days(d)).find(|d| occurs(recurrence, *d));
+                assert_eq!(recurrence.next_date(day), expected, "{:?} from {}", recurrence, day);
+                day = day.succ_opt().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::clock_data::ClockData;
+use crate::files::write_atomic;
+
+/* User settings, changed with the buttons and kept across restarts
+ *
+ * They are saved as toml in the state directory each time they change.
+ */
+
+pub const DIM_STEP: u8 = 10;
+pub const REFRESH_STEP: u32 = 10;
+pub const MIN_REFRESH: u32 = 50;
+pub const MAX_REFRESH: u32 = 200;
+// vlc volume, 256 is 100%
+pub const VOLUME_STEP: u16 = 32;
+pub const MAX_VOLUME: u16 = 512;
+
+const FILE: &str = "settings.toml";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub regular_dim: u8,   // percentage
+    pub refresh_rate: u32, // hertz (regular 7 segments and ceiling led)
+    pub ceiling_dim: u8,   // percentage
+    pub ceiling_enabled: bool,
+    pub ceiling_upwards: bool,
+    pub volume: u16,       // initial player volume
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            regular_dim: 50,
+            refresh_rate: 100,
+            ceiling_dim: 50,
+            ceiling_enabled: true,
+            ceiling_upwards: false,
+            volume: 256,
+        }
+    }
+}
+
+impl Settings {
+    // defaults when there is nothing saved yet or the file is broken
+    pub fn load(state_dir: &str) -> Self {
+        let path = Path::new(state_dir).join(FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => return Settings::default(),
+        };
+        match toml::from_str::<Settings>(&content) {
+            // the file may have been edited by hand
+            Ok(settings) => settings.clamped(),
+            Err(e) => {
+                println!("Invalid settings {}: {}, using defaults", path.display(), e);
+                Settings::default()
+            },
+        }
+    }
+
+    pub fn save(&self, state_dir: &str) -> io::Result<()> {
+        let content = toml::to_string(self).map_err(io::Error::other)?;
+        write_atomic(&Path::new(state_dir).join(FILE), content.as_bytes())
+    }
+
+    fn clamped(self) -> Self {
+        Settings {
+            regular_dim: self.regular_dim.min(100),
+            refresh_rate: self.refresh_rate.clamp(MIN_REFRESH, MAX_REFRESH),
+            ceiling_dim: self.ceiling_dim.min(100),
+            volume: self.volume.min(MAX_VOLUME),
+            ..self
+        }
+    }
+
+    // the volume goes to the player and the alarms
+    pub fn apply(&self, data: &mut ClockData) {
+        data.regular_dim = self.regular_dim;
+        data.refresh_rate = self.refresh_rate;
+        data.ceiling_dim = self.ceiling_dim;
+        data.ceiling_enabled = self.ceiling_enabled;
+        data.ceiling_upwards = self.ceiling_upwards;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved() {
+        let dir = std::env::temp_dir().join("clock-settings-test");
+        let dir = dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(dir);
+        assert_eq!(Settings::load(dir), Settings::default());
+        let settings = Settings { regular_dim: 30, ceiling_upwards: true, ..Settings::default() };
+        settings.save(dir).unwrap();
+        assert_eq!(Settings::load(dir), settings);
+        // hand edited
+        fs::write(Path::new(dir).join(FILE), "regular_dim = 255\nrefresh_rate = 5\nvolume = 9000\n").unwrap();
+        let settings = Settings::load(dir);
+        assert_eq!((settings.regular_dim, settings.refresh_rate, settings.volume), (100, MIN_REFRESH, MAX_VOLUME));
+        fs::write(Path::new(dir).join(FILE), "regular_dim = 256\n").unwrap();
+        assert_eq!(Settings::load(dir), Settings::default());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
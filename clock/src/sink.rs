@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::{BufWriter, Result, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::NativeOutput;
+
+/* Where the native player sends its samples
+ *
+ * Samples are interleaved 16 bits. The null and wav outputs have no sound card
+ * to wait for, they keep the pace of real time so streams are read as they play.
+ */
+
+pub trait Sink: Send {
+    // the output follows the rate and channels of the samples
+    fn write(&mut self, rate: u32, channels: usize, samples: &[i16]) -> Result<()>;
+    // nothing to play for a while, release the output
+    fn close(&mut self);
+}
+
+pub fn open_sink(output: NativeOutput, device: &str, path: &str) -> Box<dyn Sink> {
+    match output {
+        #[cfg(feature = "alsa-output")]
+        NativeOutput::Alsa => Box::new(Alsa::new(device)),
+        #[cfg(not(feature = "alsa-output"))]
+        NativeOutput::Alsa => {
+            println!("The clock is built without alsa output ({}), playing nothing", device);
+            Box::new(Null::new())
+        },
+        NativeOutput::Wav => Box::new(Wav::new(path)),
+        NativeOutput::Null => Box::new(Null::new()),
+    }
+}
+
+// sleeps as long as the samples would play
+struct Pacer {
+    start: Instant,
+    frames: u64,
+    rate: u32,
+    channels: usize,
+}
+
+impl Pacer {
+    fn new(rate: u32, channels: usize) -> Self {
+        Pacer { start: Instant::now(), frames: 0, rate, channels }
+    }
+
+    fn wait(&mut self, samples: usize) {
+        self.frames += (samples / self.channels.max(1)) as u64;
+        let due = self.start + Duration::from_millis(self.frames * 1000 / self.rate.max(1) as u64);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+pub struct Null {
+    pacer: Option<Pacer>,
+}
+
+impl Null {
+    pub fn new() -> Self {
+        Null { pacer: None }
+    }
+}
+
+impl Sink for Null {
+    fn write(&mut self, rate: u32, channels: usize, samples: &[i16]) -> Result<()> {
+        let pacer = match self.pacer {
+            Some(ref mut p) if p.rate == rate && p.channels == channels => p,
+            _ => self.pacer.insert(Pacer::new(rate, channels)),
+        };
+        pacer.wait(samples.len());
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.pacer = None;
+    }
+}
+
+// a new file each time the output opens
+pub struct Wav {
+    path: String,
+    file: Option<(BufWriter<File>, Pacer)>,
+    // bytes of samples in the file
+    size: u32,
+}
+
+impl Wav {
+    pub fn new(path: &str) -> Self {
+        Wav { path: path.to_string(), file: None, size: 0 }
+    }
+
+    fn header(rate: u32, channels: usize, size: u32) -> Vec<u8> {
+        let channels = channels as u16;
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(36 + size).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // pcm
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&rate.to_le_bytes());
+        header.extend_from_slice(&(rate * channels as u32 * 2).to_le_bytes());
+        header.extend_from_slice(&(channels * 2).to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&size.to_le_bytes());
+        header
+    }
+
+    // the sizes are known at the end
+    fn finish(&mut self) -> Result<()> {
+        if let Some((mut file, pacer)) = self.file.take() {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&Wav::header(pacer.rate, pacer.channels, self.size))?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Wav {
+    fn write(&mut self, rate: u32, channels: usize, samples: &[i16]) -> Result<()> {
+        let same = matches!(self.file, Some((_, ref p)) if p.rate == rate && p.channels == channels);
+        if !same {
+            self.finish()?;
+            let mut file = BufWriter::new(File::create(&self.path)?);
+            file.write_all(&Wav::header(rate, channels, 0))?;
+            self.file = Some((file, Pacer::new(rate, channels)));
+            self.size = 0;
+        }
+        if let Some((ref mut file, ref mut pacer)) = self.file {
+            for sample in samples {
+                file.write_all(&sample.to_le_bytes())?;
+            }
+            self.size = self.size.saturating_add(samples.len() as u32 * 2);
+            pacer.wait(samples.len());
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Err(e) = self.finish() {
+            println!("Cannot write {} {:?}", self.path, e);
+        }
+    }
+}
+
+impl Drop for Wav {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(feature = "alsa-output")]
+pub struct Alsa {
+    device: String,
+    pcm: Option<(alsa::PCM, u32, usize)>,
+}
+
+#[cfg(feature = "alsa-output")]
+impl Alsa {
+    pub fn new(device: &str) -> Self {
+        Alsa { device: device.to_string(), pcm: None }
+    }
+
+    fn open(&self, rate: u32, channels: usize) -> alsa::Result<alsa::PCM> {
+        use alsa::pcm::{Access, Format, HwParams};
+        let pcm = alsa::PCM::new(&self.device, alsa::Direction::Playback, false)?;
+        {
+            let params = HwParams::any(&pcm)?;
+            params.set_channels(channels as u32)?;
+            params.set_rate(rate, alsa::ValueOr::Nearest)?;
+            params.set_format(Format::s16())?;
+            params.set_access(Access::RWInterleaved)?;
+            pcm.hw_params(&params)?;
+        }
+        Ok(pcm)
+    }
+}
+
+#[cfg(feature = "alsa-output")]
+impl Sink for Alsa {
+    fn write(&mut self, rate: u32, channels: usize, samples: &[i16]) -> Result<()> {
+        let same = matches!(self.pcm, Some((_, r, c)) if r == rate && c == channels);
+        if !same {
+            self.close();
+            let pcm = self.open(rate, channels).map_err(std::io::Error::other)?;
+            self.pcm = Some((pcm, rate, channels));
+        }
+        let pcm = match self.pcm {
+            Some((ref pcm, _, _)) => pcm,
+            None => return Ok(()),
+        };
+        let io = pcm.io_i16().map_err(std::io::Error::other)?;
+        let mut written = 0;
+        while written < samples.len() {
+            match io.writei(&samples[written..]) {
+                Ok(frames) => written += frames * channels,
+                // underrun, the stream was late
+                Err(e) => pcm.try_recover(e, true).map_err(std::io::Error::other)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some((pcm, _, _)) = self.pcm.take() {
+            let _ = pcm.drain();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn wav_file() {
+        let dir = std::env::temp_dir().join("clock-sink-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("output.wav");
+        let mut wav = Wav::new(path.to_str().unwrap());
+        let start = Instant::now();
+        wav.write(8000, 2, &[1, -1, 2, -2]).unwrap();
+        wav.write(8000, 2, &vec![0; 1600]).unwrap();
+        // kept at the pace of real time, 800 frames are 100ms
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        wav.close();
+        let written = fs::read(&path).unwrap();
+        assert_eq!(written[..44], Wav::header(8000, 2, 3208)[..]);
+        assert_eq!(written.len(), 44 + 3208);
+        assert_eq!(written[44..52], [1, 0, 255, 255, 2, 0, 254, 255]);
+        // another format starts another file
+        wav.write(16000, 1, &[5]).unwrap();
+        wav.close();
+        assert_eq!(fs::read(&path).unwrap(), [&Wav::header(16000, 1, 2)[..], &[5, 0]].concat());
+    }
+}
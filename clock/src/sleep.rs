@@ -0,0 +1,114 @@
+use chrono::*;
+
+/* Sleep timer for the bedtime radio
+ *
+ * Each press chooses the next duration, after the longest one the timer is off.
+ * The volume fades out during the last minute, then the radio stops
+ * and the volume is restored for the next time.
+ */
+
+const DURATIONS: [i64; 4] = [15, 30, 45, 60];
+const FADE_SECONDS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepEvent {
+    Volume(u16),
+    // stop the radio and restore this volume
+    Stop(u16),
+}
+
+pub struct SleepTimer {
+    // index in durations
+    step: Option<usize>,
+    end: Option<DateTime<Local>>,
+    // volume before the fade
+    volume: u16,
+}
+
+impl SleepTimer {
+    pub fn new() -> Self {
+        SleepTimer { step: None, end: None, volume: 0 }
+    }
+
+    pub fn end(&self) -> Option<DateTime<Local>> {
+        self.end
+    }
+
+    // volume to play at, the one before the fade
+    pub fn volume(&self) -> u16 {
+        self.volume
+    }
+
+    // next duration in minutes, None when the timer is now off
+    pub fn cycle(&mut self, now: DateTime<Local>, volume: u16) -> Option<i64> {
+        let step = match self.step {
+            None => {
+                self.volume = volume;
+                0
+            },
+            Some(i) if i + 1 < DURATIONS.len() => i + 1,
+            Some(_) => {
+                self.cancel();
+                return None;
+            },
+        };
+        self.step = Some(step);
+        self.end = Some(now + Duration::minutes(DURATIONS[step]));
+        Some(DURATIONS[step])
+    }
+
+    // the volume to restore when the timer was on
+    pub fn cancel(&mut self) -> Option<u16> {
+        self.step = None;
+        self.end.take().map(|_| self.volume)
+    }
+
+    pub fn tick(&mut self, now: DateTime<Local>) -> Option<SleepEvent> {
+        let end = self.end?;
+        if now >= end {
+            return self.cancel().map(SleepEvent::Stop);
+        }
+        let left = (end - now).num_seconds();
+        if left >= FADE_SECONDS { return None }
+        Some(SleepEvent::Volume((self.volume as i64 * left / FADE_SECONDS) as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn cycle_and_fade() {
+        let start = Local.with_ymd_and_hms(2026, 10, 20, 22, 0, 0).unwrap();
+        let mut sleep = SleepTimer::new();
+        assert_eq!(sleep.cycle(start, 200), Some(15));
+        assert_eq!(sleep.end(), Some(start + Duration::minutes(15)));
+        assert_eq!(sleep.tick(start + Duration::minutes(13)), None);
+        // half way through the last minute
+        assert_eq!(sleep.tick(start + Duration::seconds(14 * 60 + 30)), Some(SleepEvent::Volume(100)));
+        // the volume before the fade is kept
+        assert_eq!(sleep.cycle(start, 100), Some(30));
+        assert_eq!(sleep.volume(), 200);
+        assert_eq!(sleep.cycle(start, 100), Some(45));
+        assert_eq!(sleep.cycle(start, 100), Some(60));
+        assert_eq!(sleep.cycle(start, 100), None);
+        assert_eq!(sleep.tick(start + Duration::minutes(70)), None);
+        assert_eq!(sleep.cycle(start, 150), Some(15));
+        assert_eq!(sleep.tick(start + Duration::minutes(15)), Some(SleepEvent::Stop(150)));
+        assert_eq!(sleep.end(), None);
+        assert_eq!(sleep.cancel(), None);
+    }
+
+    #[test]
+    fn cancelled() {
+        let start = Local.with_ymd_and_hms(2026, 10, 20, 22, 0, 0).unwrap();
+        let mut sleep = SleepTimer::new();
+        sleep.cycle(start, 200);
+        sleep.tick(start + Duration::seconds(14 * 60 + 30));
+        // back to the volume before the fade
+        assert_eq!(sleep.cancel(), Some(200));
+        assert_eq!(sleep.tick(start + Duration::minutes(15)), None);
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use crate::config::{RadioConfig, StationConfig};
+
+/* Radio stations
+ *
+ * Stations come from the configuration and from m3u or pls playlists,
+ * local files or urls. A station url that points to a playlist, as many
+ * radios publish, is replaced by the first stream inside. Local files are
+ * read at startup, urls are fetched once in the background so the clock
+ * doesn't wait for the network. What cannot be read is kept as is: the
+ * player may still understand it.
+ */
+
+const DEFAULT_NAME: &str = "France Inter";
+const DEFAULT_URL: &str = "http://direct.franceinter.fr/live/franceinter-hifi.aac";
+const DEFAULT_LABEL: &str = "InTE";
+
+// the clock has 4 digits
+const LABEL_LENGTH: usize = 4;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+    // shown on the display when the station is selected
+    pub label: String,
+    // added to the volume while this station plays
+    pub volume_offset: i16,
+}
+
+impl Station {
+    fn new(name: &str, url: &str, label: Option<&str>, volume_offset: i16) -> Self {
+        let label = label.unwrap_or(name);
+        Station {
+            name: name.to_string(),
+            url: url.to_string(),
+            label: label.chars().take(LABEL_LENGTH).collect(),
+            volume_offset,
+        }
+    }
+
+    fn from_config(config: &StationConfig, fetch: bool) -> Self {
+        let url = resolve(&config.url, fetch);
+        let name = config.name.as_deref().unwrap_or(&config.url);
+        Station::new(name, &url, config.label.as_deref(), config.volume_offset)
+    }
+}
+
+// configured stations then playlists, never empty
+pub fn load_stations(config: &RadioConfig) -> Vec<Station> {
+    stations(config, true)
+}
+
+// the same without the network, playlist urls are kept and remote playlists are missing
+pub fn local_stations(config: &RadioConfig) -> Vec<Station> {
+    stations(config, false)
+}
+
+fn stations(config: &RadioConfig, fetch: bool) -> Vec<Station> {
+    let mut stations: Vec<Station> = config.stations.iter().map(|s| Station::from_config(s, fetch)).collect();
+    for playlist in &config.playlists {
+        if !fetch && is_remote(playlist) { continue }
+        match read(playlist) {
+            Ok(content) => stations.extend(parse_playlist(playlist, &content).into_iter()
+                .map(|(name, url)| Station::new(name.as_deref().unwrap_or(&url), &url, None, 0))),
+            Err(e) => println!("Cannot read playlist {}: {:?}", playlist, e),
+        }
+    }
+    if stations.is_empty() {
+        stations.push(Station::new(DEFAULT_NAME, DEFAULT_URL, Some(DEFAULT_LABEL), 0));
+    }
+    stations
+}
+
+// first stream of a playlist url, other urls as they are
+fn resolve(url: &str, fetch: bool) -> String {
+    if !is_playlist(url) || (!fetch && is_remote(url)) { return url.to_string() }
+    let entries = match read(url) {
+        Ok(content) => parse_playlist(url, &content),
+        Err(e) => {
+            println!("Cannot read playlist {}: {:?}", url, e);
+            return url.to_string();
+        },
+    };
+    match entries.into_iter().next() {
+        Some((_, stream)) => stream,
+        None => {
+            println!("No stream in playlist {}", url);
+            url.to_string()
+        },
+    }
+}
+
+// m3u8 are hls streams, not lists of stations
+fn is_playlist(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    path.ends_with(".m3u") || path.ends_with(".pls")
+}
+
+fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+fn read(location: &str) -> io::Result<String> {
+    if !is_remote(location) {
+        return fs::read_to_string(location);
+    }
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(FETCH_TIMEOUT))
+        .build()
+        .into();
+    let mut response = agent.get(location).call().map_err(io::Error::other)?;
+    response.body_mut().read_to_string().map_err(io::Error::other)
+}
+
+// (name, url) of the entries, in order
+fn parse_playlist(location: &str, content: &str) -> Vec<(Option<String>, String)> {
+    let pls = location.split(['?', '#']).next().unwrap_or(location).to_lowercase().ends_with(".pls")
+        || content.trim_start().starts_with("[playlist]");
+    if pls { parse_pls(content) } else { parse_m3u(content) }
+}
+
+// the name is in the #EXTINF line before the url
+fn parse_m3u(content: &str) -> Vec<(Option<String>, String)> {
+    let mut entries = Vec::new();
+    let mut name = None;
+    for line in content.lines().map(|l| l.trim().trim_start_matches('\u{feff}')) {
+        if line.is_empty() { continue }
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            name = info.split_once(',').map(|(_, n)| n.trim().to_string()).filter(|n| !n.is_empty());
+        } else if !line.starts_with('#') {
+            entries.push((name.take(), line.to_string()));
+        }
+    }
+    entries
+}
+
+// FileN and TitleN keys, numbered from 1
+fn parse_pls(content: &str) -> Vec<(Option<String>, String)> {
+    let mut files = Vec::new();
+    let mut titles = Vec::new();
+    for line in content.lines() {
+        let (key, value) = match line.trim().split_once('=') {
+            Some((k, v)) => (k.trim().to_lowercase(), v.trim().to_string()),
+            None => continue,
+        };
+        if let Some(n) = key.strip_prefix("file").and_then(|n| n.parse::<usize>().ok()) {
+            files.push((n, value));
+        } else if let Some(n) = key.strip_prefix("title").and_then(|n| n.parse::<usize>().ok()) {
+            titles.push((n, value));
+        }
+    }
+    files.sort_by_key(|(n, _)| *n);
+    files.into_iter().map(|(n, url)| {
+        let title = titles.iter().find(|(t, _)| *t == n).map(|(_, title)| title.clone());
+        (title.filter(|t| !t.is_empty()), url)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    fn write(dir: &PathBuf, name: &str, content: &str) -> String {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // serves a playlist once
+    fn server(content: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/live.pls", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            // the request head, up to the empty line
+            let head = BufReader::new(socket.try_clone().unwrap()).lines().map_while(|l| l.ok()).take_while(|l| !l.is_empty());
+            assert!(head.count() > 0);
+            write!(socket, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", content.len(), content).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn playlists() {
+        assert_eq!(parse_playlist("a.pls", "[playlist]\nFile2=http://b/stream\nTitle2=Bee\nFile1=http://a/stream\nTitle1=\n"),
+                   vec![(None, "http://a/stream".to_string()), (Some("Bee".to_string()), "http://b/stream".to_string())]);
+        assert_eq!(parse_playlist("a", "\u{feff}#EXTM3U\n#EXTINF:-1,Jazz Radio\nhttp://jazz/live.mp3\n\nhttp://nameless/x\n"),
+                   vec![(Some("Jazz Radio".to_string()), "http://jazz/live.mp3".to_string()), (None, "http://nameless/x".to_string())]);
+        assert!(is_playlist("http://radio/live.PLS?id=1"));
+        assert!(!is_playlist("http://radio/live.m3u8"));
+    }
+
+    #[test]
+    fn configured() {
+        let dir = std::env::temp_dir().join("clock-stations-test");
+        let pls = write(&dir, "fip.pls", "[playlist]\nFile1=http://a/stream\nTitle1=Radio A\n");
+        let m3u = write(&dir, "jazz.m3u", "#EXTM3U\n#EXTINF:-1,Jazz Radio\nhttp://jazz/live.mp3\nhttp://nameless/x\n");
+        let radio: RadioConfig = toml::from_str(&format!(r#"
+            playlists = ["{}", "{}"]
+            [[stations]]
+            name = "Fip"
+            url = "{}"
+            volume_offset = 40
+            [[stations]]
+            url = "http://plain/stream.m3u8"
+            label = "PLAIN"
+        "#, m3u, dir.join("missing.m3u").display(), pls)).unwrap();
+        let stations = load_stations(&radio);
+        assert_eq!(stations, vec![
+            Station::new("Fip", "http://a/stream", None, 40),
+            Station::new("http://plain/stream.m3u8", "http://plain/stream.m3u8", Some("PLAI"), 0),
+            Station::new("Jazz Radio", "http://jazz/live.mp3", None, 0),
+            Station::new("http://nameless/x", "http://nameless/x", None, 0),
+        ]);
+        assert_eq!(stations[2].label, "Jazz");
+        // local files don't wait for the network
+        assert_eq!(local_stations(&radio), stations);
+        assert_eq!(load_stations(&RadioConfig::default())[0].label, DEFAULT_LABEL);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remote() {
+        let url = server("[playlist]\nFile1=http://b/stream\n");
+        let radio: RadioConfig = toml::from_str(&format!("[[stations]]\nname = \"Bee\"\nurl = \"{}\"\n", url)).unwrap();
+        // kept until resolved
+        assert_eq!(local_stations(&radio)[0].url, url);
+        assert_eq!(load_stations(&radio)[0].url, "http://b/stream");
+        // gone, kept as is
+        assert_eq!(load_stations(&radio)[0].url, url);
+    }
+}
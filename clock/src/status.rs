@@ -0,0 +1,70 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+
+use crate::alarm::Indicator;
+use crate::files::write_atomic;
+use crate::player::PlayerState;
+
+/* Status of the clock, for other programs
+ *
+ * Written as toml in the state directory each time it changes,
+ * `clock status` prints it.
+ */
+
+pub const FILE: &str = "status.toml";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub alarms_enabled: bool,
+    pub alarm: Indicator,
+    pub next_alarm: Option<DateTime<Local>>,
+    pub skipped: Option<DateTime<Local>>,
+    pub nap: Option<DateTime<Local>>,
+    pub missed: Option<DateTime<Local>>,
+    // end of the sleep timer
+    pub sleep: Option<DateTime<Local>>,
+    // last known, it is asked from time to time
+    pub player: PlayerState,
+    // name of the selected station
+    pub station: String,
+}
+
+impl Status {
+    pub fn save(&self, state_dir: &str) -> io::Result<()> {
+        let content = toml::to_string(self).map_err(io::Error::other)?;
+        write_atomic(&Path::new(state_dir).join(FILE), content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn saved() {
+        let dir = std::env::temp_dir().join("clock-status-test");
+        let dir = dir.to_str().unwrap();
+        let status = Status {
+            alarms_enabled: true,
+            alarm: Indicator::Skipped,
+            next_alarm: Some(Local::now()),
+            skipped: None,
+            nap: None,
+            missed: None,
+            sleep: None,
+            player: PlayerState { volume: Some(256), title: Some("news".to_string()), ..PlayerState::default() },
+            station: "fip".to_string(),
+        };
+        status.save(dir).unwrap();
+        let saved: toml::Table = toml::from_str(&fs::read_to_string(Path::new(dir).join(FILE)).unwrap()).unwrap();
+        assert_eq!(saved["alarm"].as_str(), Some("skipped"));
+        assert_eq!(saved["station"].as_str(), Some("fip"));
+        assert!(saved["next_alarm"].is_str());
+        assert!(!saved.contains_key("nap"));
+        assert_eq!(saved["player"]["title"].as_str(), Some("news"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
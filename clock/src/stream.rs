@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/* Opening a stream to read it ourselves
+ *
+ * Local files, http and https urls. Shoutcast servers answer http requests
+ * with an "ICY 200 OK" status line that http clients refuse, so plain http
+ * is spoken here. Nobody uses https for those.
+ * Icy metadata is asked for, the caller strips it (see icy.rs).
+ */
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// a stream silent for this long is broken
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_REDIRECTS: usize = 5;
+
+pub struct Stream {
+    pub reader: Box<dyn Read + Send>,
+    pub content_type: Option<String>,
+    // audio bytes between icy metadata blocks
+    pub metaint: Option<usize>,
+}
+
+pub fn open(location: &str) -> Result<Stream> {
+    if location.starts_with("http://") {
+        return open_http(location, MAX_REDIRECTS);
+    }
+    if location.starts_with("https://") {
+        return open_https(location);
+    }
+    let path = location.strip_prefix("file://").unwrap_or(location);
+    Ok(Stream { reader: Box::new(File::open(path)?), content_type: None, metaint: None })
+}
+
+fn open_http(url: &str, redirects: usize) -> Result<Stream> {
+    let rest = url.trim_start_matches("http://");
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    let address = address.to_socket_addrs()?.next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no address for {}", host)))?;
+    let mut socket = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    socket.set_read_timeout(Some(READ_TIMEOUT))?;
+    write!(socket, "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: clock\r\nAccept: */*\r\nIcy-MetaData: 1\r\nConnection: close\r\n\r\n",
+           path, host)?;
+
+    let mut reader = BufReader::new(socket);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    // HTTP/1.1 200 OK or ICY 200 OK
+    let code = status.split_whitespace().nth(1).and_then(|c| c.parse::<u16>().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("bad answer from {}: {}", url, status.trim())))?;
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 { break }
+        let line = line.trim();
+        if line.is_empty() { break }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    match code {
+        200 => Ok(Stream {
+            reader: Box::new(reader),
+            content_type: headers.remove("content-type"),
+            metaint: headers.get("icy-metaint").and_then(|m| m.parse().ok()),
+        }),
+        301 | 302 | 303 | 307 | 308 if redirects > 0 => match headers.get("location").map(|l| resolve(host, path, l)) {
+            Some(location) if location.starts_with("http://") => open_http(&location, redirects - 1),
+            Some(location) => open_https(&location),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("redirect without location from {}", url))),
+        },
+        _ => Err(Error::other(format!("http {} from {}", code, url))),
+    }
+}
+
+// a location relative to the http request, never a local file
+fn resolve(host: &str, path: &str, location: &str) -> String {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        location.to_string()
+    } else if location.starts_with("//") {
+        format!("http:{}", location)
+    } else if location.starts_with('/') {
+        format!("http://{}{}", host, location)
+    } else {
+        let path = path.split(['?', '#']).next().unwrap_or("/");
+        let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        format!("http://{}{}{}", host, if dir.is_empty() { "/" } else { dir }, location)
+    }
+}
+
+fn open_https(url: &str) -> Result<Stream> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .build()
+        .into();
+    let response = agent.get(url).header("Icy-MetaData", "1").call().map_err(Error::other)?;
+    let header = |name: &str| response.headers().get(name).and_then(|t| t.to_str().ok()).map(|t| t.to_string());
+    let content_type = header("content-type");
+    let metaint = header("icy-metaint").and_then(|m| m.parse().ok());
+    Ok(Stream { reader: Box::new(response.into_body().into_reader()), content_type, metaint })
+}
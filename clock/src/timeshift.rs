@@ -0,0 +1,429 @@
+use chrono::{DateTime, Duration, Local, Timelike};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::config::TimeshiftConfig;
+use crate::icy::IcyReader;
+use crate::stream;
+
+/* Radio recorded before the alarm, played from a given time
+ *
+ * The stream is written to a directory, one file per minute named after the
+ * time it starts, the first one starting with the recording and the others on
+ * the minute. Files older than keep_minutes are removed as the recording goes.
+ * A small http server on localhost plays the recording from a time and follows
+ * it as it grows: http://127.0.0.1:<port>/<unix time>. Every player backend
+ * reads it like a station.
+ */
+
+// one file per minute
+const SEGMENT: i64 = 60;
+// between two connections to a broken stream
+const RETRY: std::time::Duration = std::time::Duration::from_secs(5);
+// waiting for the recording to grow
+const POLL: std::time::Duration = std::time::Duration::from_millis(200);
+
+#[derive(Default)]
+struct Shared {
+    // recorded stream, None when not recording
+    url: Option<String>,
+    // changes with each recording, older recorder threads stop
+    generation: u64,
+    content_type: Option<String>,
+}
+
+pub struct Timeshift {
+    dir: PathBuf,
+    port: u16,
+    marks: Vec<u32>,
+    lead: Duration,
+    max_delay: Duration,
+    keep: Duration,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Timeshift {
+    // None when not configured or when the port is taken
+    pub fn new(config: &TimeshiftConfig) -> Option<Self> {
+        let dir = PathBuf::from(config.dir.as_ref()?);
+        let listener = match TcpListener::bind(("127.0.0.1", config.port)) {
+            Ok(listener) => listener,
+            Err(e) => { println!("Cannot serve the recording on port {} {:?}", config.port, e); return None },
+        };
+        // the port given by the system when 0
+        let port = listener.local_addr().ok()?.port();
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let server_dir = dir.clone();
+        let server_shared = shared.clone();
+        thread::spawn(move || serve(listener, server_dir, server_shared));
+        Some(Timeshift {
+            dir,
+            port,
+            marks: config.marks.clone(),
+            lead: Duration::minutes(config.lead_minutes),
+            max_delay: Duration::minutes(config.max_delay_minutes),
+            keep: Duration::minutes(config.keep_minutes),
+            shared,
+        })
+    }
+
+    // how long before the alarm the recording starts
+    pub fn lead(&self) -> Duration {
+        self.lead
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.shared.lock().expect("poisoned mutex 23").url.is_some()
+    }
+
+    // the previous recording is removed when the stream changes
+    pub fn record(&mut self, url: &str) {
+        let generation = {
+            let mut shared = self.shared.lock().expect("poisoned mutex 24");
+            if shared.url.as_deref() == Some(url) { return }
+            shared.url = Some(url.to_string());
+            shared.generation += 1;
+            shared.content_type = None;
+            shared.generation
+        };
+        if let Err(e) = clear(&self.dir) {
+            println!("Cannot clear the recording in {} {:?}", self.dir.display(), e);
+        }
+        println!("Recording {}", url);
+        let dir = self.dir.clone();
+        let url = url.to_string();
+        let shared = self.shared.clone();
+        let keep = self.keep;
+        thread::spawn(move || record(dir, url, generation, shared, keep));
+    }
+
+    // the recording stays until the next one
+    pub fn stop(&mut self) {
+        let mut shared = self.shared.lock().expect("poisoned mutex 25");
+        if shared.url.is_none() { return }
+        shared.url = None;
+        shared.generation += 1;
+        println!("Recording stopped");
+    }
+
+    // the recording from the last mark, None when it doesn't hold the mark
+    pub fn url(&self, now: DateTime<Local>) -> Option<String> {
+        if !self.is_recording() { return None }
+        let mark = last_mark(now, &self.marks, self.max_delay)?.timestamp();
+        let start = segment_at(&self.dir, mark)?;
+        if !covers(start, mark) { return None }
+        Some(format!("http://127.0.0.1:{}/{}", self.port, mark))
+    }
+}
+
+// the latest mark, not older than max_delay
+fn last_mark(now: DateTime<Local>, marks: &[u32], max_delay: Duration) -> Option<DateTime<Local>> {
+    let minute = now.with_second(0)?.with_nanosecond(0)?;
+    (0..=max_delay.num_minutes()).map(|m| minute - Duration::minutes(m))
+        .find(|t| marks.contains(&t.minute()))
+}
+
+// a file lasts until the next minute
+fn covers(start: i64, time: i64) -> bool {
+    start <= time && time < start - start.rem_euclid(SEGMENT) + SEGMENT
+}
+
+fn segment_path(dir: &Path, start: i64) -> PathBuf {
+    dir.join(format!("{}.rec", start))
+}
+
+// start times of the files, oldest first
+fn segments(dir: &Path) -> Vec<i64> {
+    let mut starts: Vec<i64> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_suffix(".rec")?.parse().ok()
+        }).collect(),
+        Err(_) => Vec::new(),
+    };
+    starts.sort_unstable();
+    starts
+}
+
+// the file holding this time
+fn segment_at(dir: &Path, time: i64) -> Option<i64> {
+    segments(dir).into_iter().rev().find(|start| *start <= time)
+}
+
+fn next_segment(dir: &Path, start: i64) -> Option<i64> {
+    segments(dir).into_iter().find(|s| *s > start)
+}
+
+fn clear(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for start in segments(dir) {
+        fs::remove_file(segment_path(dir, start))?;
+    }
+    Ok(())
+}
+
+// this recording is still going on
+fn current(shared: &Mutex<Shared>, generation: u64) -> bool {
+    let shared = shared.lock().expect("poisoned mutex 26");
+    shared.generation == generation && shared.url.is_some()
+}
+
+// reconnects until the recording stops
+fn record(dir: PathBuf, url: String, generation: u64, shared: Arc<Mutex<Shared>>, keep: Duration) {
+    let mut segment = None;
+    let mut buffer = [0; 16384];
+    while current(&shared, generation) {
+        let stream = match stream::open(&url) {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Cannot record {} {:?}", url, e);
+                thread::sleep(RETRY);
+                continue;
+            },
+        };
+        shared.lock().expect("poisoned mutex 27").content_type = stream.content_type.clone();
+        let mut reader: Box<dyn Read> = match stream.metaint {
+            Some(metaint) => Box::new(IcyReader::new(stream.reader, metaint, |_| ())),
+            None => stream.reader,
+        };
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => { println!("Recorded stream {} ended", url); break },
+                Ok(n) => n,
+                Err(e) => { println!("Recording error {} {:?}", url, e); break },
+            };
+            if !current(&shared, generation) { return }
+            if let Err(e) = write_segment(&dir, &mut segment, &buffer[..n], Local::now().timestamp(), keep) {
+                println!("Cannot write the recording in {} {:?}", dir.display(), e);
+            }
+        }
+        thread::sleep(RETRY);
+    }
+}
+
+// a new file each minute, old ones are removed
+fn write_segment(dir: &Path, segment: &mut Option<(i64, File)>, data: &[u8], now: i64, keep: Duration) -> io::Result<()> {
+    let minute = now - now.rem_euclid(SEGMENT);
+    let file = match segment {
+        Some((start, ref mut file)) if *start >= minute => file,
+        _ => {
+            let start = if segment.is_none() { now } else { minute };
+            for old in segments(dir).into_iter().filter(|s| *s < now - keep.num_seconds()) {
+                fs::remove_file(segment_path(dir, old))?;
+            }
+            &mut segment.insert((start, File::create(segment_path(dir, start))?)).1
+        },
+    };
+    file.write_all(data)
+}
+
+fn serve(listener: TcpListener, dir: PathBuf, shared: Arc<Mutex<Shared>>) {
+    for socket in listener.incoming() {
+        match socket {
+            Ok(socket) => {
+                let dir = dir.clone();
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    // players hang up when they stop
+                    match answer(socket, &dir, &shared) {
+                        Err(e) if e.kind() != ErrorKind::BrokenPipe && e.kind() != ErrorKind::ConnectionReset =>
+                            println!("Cannot send the recording {:?}", e),
+                        _ => (),
+                    }
+                });
+            },
+            Err(e) => println!("Recording server error {:?}", e),
+        }
+    }
+}
+
+// GET /<unix time>, the recording from that time until it stops
+fn answer(mut socket: TcpStream, dir: &Path, shared: &Mutex<Shared>) -> io::Result<()> {
+    let mut reader = BufReader::new(socket.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // headers are not needed
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() { break }
+    }
+    let time = request.split_whitespace().nth(1).and_then(|path| path.trim_start_matches('/').parse::<i64>().ok());
+    let mut start = match time.and_then(|t| segment_at(dir, t)) {
+        Some(start) => start,
+        None => return write!(socket, "HTTP/1.0 404 Not Found\r\n\r\n"),
+    };
+    let (generation, content_type) = {
+        let shared = shared.lock().expect("poisoned mutex 28");
+        (shared.generation, shared.content_type.clone().unwrap_or_else(|| "audio/mpeg".to_string()))
+    };
+    write!(socket, "HTTP/1.0 200 OK\r\nContent-Type: {}\r\n\r\n", content_type)?;
+    let mut file = File::open(segment_path(dir, start))?;
+    loop {
+        io::copy(&mut file, &mut socket)?;
+        match next_segment(dir, start) {
+            // the end of the file may have been written meanwhile
+            Some(next) => {
+                io::copy(&mut file, &mut socket)?;
+                start = next;
+                file = File::open(segment_path(dir, start))?;
+            },
+            None if current(shared, generation) => thread::sleep(POLL),
+            None => {
+                io::copy(&mut file, &mut socket)?;
+                return Ok(());
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 3, 10, hour, minute, second).unwrap()
+    }
+
+    fn timeshift(name: &str, marks: Vec<u32>) -> Timeshift {
+        let dir = std::env::temp_dir().join(format!("clock-timeshift-{}-test", name));
+        clear(&dir).unwrap();
+        let config = TimeshiftConfig { dir: Some(dir.to_str().unwrap().to_string()), port: 0, marks, ..TimeshiftConfig::default() };
+        Timeshift::new(&config).unwrap()
+    }
+
+    // a radio that never ends, the bytes count up
+    fn radio() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/live", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let mut socket = socket.unwrap();
+                thread::spawn(move || {
+                    let reader = BufReader::new(socket.try_clone().unwrap());
+                    reader.lines().map_while(|l| l.ok()).take_while(|l| !l.is_empty()).for_each(drop);
+                    socket.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: audio/aac\r\n\r\n").unwrap();
+                    let bytes: Vec<u8> = (0..=250).collect();
+                    // until the recorder hangs up
+                    while socket.write_all(&bytes).is_ok() {
+                        thread::sleep(std::time::Duration::from_millis(10));
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn marks() {
+        let max_delay = Duration::minutes(15);
+        assert_eq!(last_mark(at(7, 3, 20), &[0, 30], max_delay), Some(at(7, 0, 0)));
+        assert_eq!(last_mark(at(7, 30, 59), &[0, 30], max_delay), Some(at(7, 30, 0)));
+        assert_eq!(last_mark(at(7, 15, 0), &[0, 30], max_delay), Some(at(7, 0, 0)));
+        assert_eq!(last_mark(at(7, 20, 0), &[0, 30], max_delay), None);
+        assert_eq!(last_mark(at(0, 5, 0), &[55], max_delay), Some(at(0, 5, 0) - Duration::minutes(10)));
+    }
+
+    #[test]
+    fn covering() {
+        assert!(covers(120, 120) && covers(120, 179));
+        assert!(!covers(120, 180) && !covers(120, 119));
+        // the first file starts with the recording
+        assert!(!covers(130, 129) && covers(130, 130) && !covers(130, 180));
+    }
+
+    #[test]
+    fn files() {
+        let dir = std::env::temp_dir().join("clock-timeshift-files-test");
+        clear(&dir).unwrap();
+        for name in ["180.rec", "60.rec", "other.txt", "x.rec"] {
+            File::create(dir.join(name)).unwrap();
+        }
+        assert_eq!(segments(&dir), [60, 180]);
+        assert_eq!((segment_at(&dir, 100), segment_at(&dir, 180), segment_at(&dir, 30)), (Some(60), Some(180), None));
+        assert_eq!((next_segment(&dir, 60), next_segment(&dir, 180)), (Some(180), None));
+        clear(&dir).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn written() {
+        let dir = std::env::temp_dir().join("clock-timeshift-written-test");
+        clear(&dir).unwrap();
+        let keep = Duration::minutes(2);
+        for start in [0, 60, 100] {
+            File::create(segment_path(&dir, start)).unwrap();
+        }
+        let mut segment = None;
+        // the first file starts with the recording, the ones out of the window are removed
+        write_segment(&dir, &mut segment, b"ab", 230, keep).unwrap();
+        assert_eq!(segments(&dir), [230]);
+        write_segment(&dir, &mut segment, b"cd", 239, keep).unwrap();
+        assert_eq!(segments(&dir), [230]);
+        // then one each minute
+        write_segment(&dir, &mut segment, b"ef", 240, keep).unwrap();
+        assert_eq!(fs::read(segment_path(&dir, 230)).unwrap(), b"abcd");
+        write_segment(&dir, &mut segment, b"gh", 300, keep).unwrap();
+        assert_eq!(segments(&dir), [230, 240, 300]);
+        // the old ones go, the current one and the ones in the window stay
+        write_segment(&dir, &mut segment, b"ij", 370, keep).unwrap();
+        assert_eq!(segments(&dir), [300, 360]);
+        write_segment(&dir, &mut segment, b"kl", 375, keep).unwrap();
+        drop(segment);
+        assert_eq!(fs::read(segment_path(&dir, 300)).unwrap(), b"gh");
+        assert_eq!(fs::read(segment_path(&dir, 360)).unwrap(), b"ijkl");
+    }
+
+    #[test]
+    fn mark_url() {
+        let mut timeshift = timeshift("url", vec![30]);
+        let mark = at(7, 30, 0).timestamp();
+        File::create(segment_path(&timeshift.dir, mark - 65)).unwrap();
+        File::create(segment_path(&timeshift.dir, mark - 60)).unwrap();
+        timeshift.shared.lock().unwrap().url = Some("http://radio".to_string());
+        // the file holding the mark is missing
+        assert_eq!(timeshift.url(at(7, 31, 0)), None);
+        File::create(segment_path(&timeshift.dir, mark)).unwrap();
+        timeshift.stop();
+        assert_eq!(timeshift.url(at(7, 31, 0)), None);
+        timeshift.shared.lock().unwrap().url = Some("http://radio".to_string());
+        assert_eq!(timeshift.url(at(7, 31, 0)), Some(format!("http://127.0.0.1:{}/{}", timeshift.port, mark)));
+        assert_ne!(timeshift.port, 0);
+        // too late for the mark
+        assert_eq!(timeshift.url(at(8, 0, 0)), None);
+        // the recording started after the mark
+        clear(&timeshift.dir).unwrap();
+        File::create(segment_path(&timeshift.dir, mark + 1)).unwrap();
+        assert_eq!(timeshift.url(at(7, 31, 0)), None);
+    }
+
+    #[test]
+    fn recorded_stream() {
+        let mut timeshift = timeshift("stream", vec![0]);
+        timeshift.record(&radio());
+        assert!(timeshift.is_recording());
+        let mut start = None;
+        for _ in 0..50 {
+            start = segments(&timeshift.dir).first().copied();
+            if start.is_some_and(|s| fs::metadata(segment_path(&timeshift.dir, s)).unwrap().len() > 1000) { break }
+            thread::sleep(POLL / 2);
+        }
+        let start = start.unwrap();
+        let base = format!("http://127.0.0.1:{}", timeshift.port);
+        assert!(stream::open(&format!("{}/{}", base, start - 1)).is_err());
+        let recorded = stream::open(&format!("{}/{}", base, start)).unwrap();
+        assert_eq!(recorded.content_type.as_deref(), Some("audio/aac"));
+        // the recording is followed as it grows, until it stops
+        let mut reader = recorded.reader;
+        let mut bytes = vec![0; 2000];
+        reader.read_exact(&mut bytes).unwrap();
+        timeshift.stop();
+        reader.read_to_end(&mut bytes).unwrap();
+        assert!(bytes.len() > 2000);
+        assert!(bytes.iter().enumerate().all(|(i, b)| *b as usize == i % 251));
+    }
+}
@@ -0,0 +1,267 @@
+use std::time::*;
+
+use crate::action::*;
+use crate::input::Press;
+use crate::menu::*;
+use crate::settings::Settings;
+
+/* User interface state machine
+ *
+ *            B1                      B2
+ *  Special <----> Normal <-------------------> Settings
+ *     |           ^ ^  |                           |
+ *     |  B1/Time/ | |  | B1 long                   |
+ *     |   Snooze  | |  v                           |
+ *     |           | Nap                            |
+ *     |             ^                              |
+ *     +-------------+------------------------------+
+ *               inactivity timeout
+ *
+ * Settings mode is the settings menu (see menu.rs), special mode changes settings directly.
+ * Nap mode sets a nap: Left/Right change its length, Time starts it (0 cancels it), Snooze leaves.
+ *
+ * The state machine doesn't touch any hardware, it returns commands
+ * that the main thread executes. This way it only depends on events and time.
+ */
+
+// how long mode feedback stays on the display
+pub const FEEDBACK_DURATION: Duration = Duration::from_secs(2);
+
+// nap length in minutes
+const NAP_DEFAULT: u32 = 20;
+const NAP_STEP: u32 = 5;
+const NAP_MAX: u32 = 180;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // do what the button is bound to
+    Run(Action),
+    // text on the led display, blinking or not, for some time or until cleared
+    Show(String, bool, Option<Duration>),
+    ClearOverlay,
+    // settings changed, apply and save them
+    Apply(Settings),
+    // nap for some minutes, 0 cancels the nap
+    Nap(u32),
+}
+
+pub struct Ui {
+    mode: Mode,
+    bindings: Bindings,
+    // return to normal mode after this time without any press
+    timeout: Duration,
+    last_press: Instant,
+    settings: Settings,
+    menu: Menu,
+    // nap length being chosen
+    nap: u32,
+}
+
+impl Ui {
+    pub fn new(bindings: Bindings, timeout: Duration, settings: Settings, now: Instant) -> Self {
+        Ui { mode: Mode::Normal, bindings, timeout, last_press: now, settings, menu: Menu::new(), nap: NAP_DEFAULT }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn press(&mut self, press: Press, now: Instant) -> Vec<Command> {
+        self.last_press = now;
+        match self.bindings.action(self.mode, press) {
+            Action::Nothing => Vec::new(),
+            Action::NormalMode => self.switch(Mode::Normal),
+            Action::SpecialMode => self.switch(Mode::Special),
+            Action::SettingsMode => self.switch(Mode::Settings),
+            Action::NapMode => self.switch(Mode::Nap),
+            // leaving the menu unless a value is being edited
+            Action::MenuBack if !self.menu.is_editing() => self.switch(Mode::Normal),
+            action @ (Action::MenuPrevious | Action::MenuNext | Action::MenuSelect | Action::MenuBack) =>
+                match self.mode {
+                    Mode::Settings => self.menu.action(action, &mut self.settings),
+                    Mode::Nap => self.nap(action),
+                    _ => Vec::new(),
+                },
+            action @ (Action::DimUp | Action::DimDown | Action::RefreshRateUp | Action::RefreshRateDown
+                      | Action::ToggleCeiling) => self.adjust(action),
+            action => vec![Command::Run(action)],
+        }
+    }
+
+    // quick settings changes, with feedback
+    fn adjust(&mut self, action: Action) -> Vec<Command> {
+        let settings = &mut self.settings;
+        let feedback = match action {
+            Action::DimUp | Action::DimDown => {
+                let up = action == Action::DimUp;
+                settings.regular_dim = step_dim(settings.regular_dim, up);
+                settings.ceiling_dim = step_dim(settings.ceiling_dim, up);
+                format!("d{:>3}", settings.regular_dim)
+            },
+            Action::RefreshRateUp | Action::RefreshRateDown => {
+                settings.refresh_rate = step_refresh(settings.refresh_rate, action == Action::RefreshRateUp);
+                format!("r{:>3}", settings.refresh_rate)
+            },
+            _ => {
+                settings.ceiling_enabled = !settings.ceiling_enabled;
+                if settings.ceiling_enabled { "C on" } else { "COFF" }.to_string()
+            },
+        };
+        vec![Command::Apply(settings.clone()), Command::Show(feedback, false, Some(FEEDBACK_DURATION))]
+    }
+
+    fn nap(&mut self, action: Action) -> Vec<Command> {
+        match action {
+            Action::MenuPrevious => self.nap = self.nap.saturating_sub(NAP_STEP),
+            Action::MenuNext => self.nap = (self.nap + NAP_STEP).min(NAP_MAX),
+            _ => {
+                let mut commands = self.switch(Mode::Normal);
+                commands.push(Command::Nap(self.nap));
+                return commands;
+            },
+        }
+        vec![self.show_nap()]
+    }
+
+    fn show_nap(&self) -> Command {
+        let text = if self.nap == 0 { "nOFF".to_string() } else { format!("n{:>3}", self.nap) };
+        Command::Show(text, true, None)
+    }
+
+    // back to normal mode, whatever we were doing
+    pub fn reset(&mut self) -> Vec<Command> {
+        self.switch(Mode::Normal)
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Vec<Command> {
+        if self.mode != Mode::Normal && now.duration_since(self.last_press) >= self.timeout {
+            return self.switch(Mode::Normal);
+        }
+        Vec::new()
+    }
+
+    fn switch(&mut self, mode: Mode) -> Vec<Command> {
+        if mode == self.mode { return Vec::new() }
+        let mut commands = self.exit();
+        self.mode = mode;
+        commands.append(&mut self.entry());
+        commands
+    }
+
+    fn entry(&mut self) -> Vec<Command> {
+        match self.mode {
+            Mode::Normal => vec![Command::Show("norm".into(), false, Some(FEEDBACK_DURATION))],
+            Mode::Special => vec![Command::Show("SPEC".into(), false, Some(FEEDBACK_DURATION))],
+            Mode::Settings => self.menu.open(),
+            Mode::Nap => {
+                self.nap = NAP_DEFAULT;
+                vec![self.show_nap()]
+            },
+        }
+    }
+
+    fn exit(&mut self) -> Vec<Command> {
+        match self.mode {
+            Mode::Normal => Vec::new(),
+            Mode::Special | Mode::Nap => vec![Command::ClearOverlay],
+            // an unsaved value is lost
+            Mode::Settings => {
+                self.menu.close();
+                vec![Command::ClearOverlay]
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Gesture;
+    use crate::keys::Button;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn short(button: Button) -> Press {
+        Press { button, gesture: Gesture::Short }
+    }
+
+    fn show(text: &str, duration: Option<Duration>) -> Command {
+        Command::Show(text.into(), false, duration)
+    }
+
+    #[test]
+    fn special_mode() {
+        let start = Instant::now();
+        let mut ui = Ui::new(Bindings::new(&[]), TIMEOUT, Settings::default(), start);
+        assert_eq!(ui.press(short(Button::B1), start), vec![show("SPEC", Some(FEEDBACK_DURATION))]);
+        assert_eq!(ui.mode(), Mode::Special);
+        let commands = ui.press(short(Button::Right), start);
+        let settings = Settings { regular_dim: 60, ceiling_dim: 60, ..Settings::default() };
+        assert_eq!(commands, vec![Command::Apply(settings), show("d 60", Some(FEEDBACK_DURATION))]);
+        // snooze works in every mode
+        assert_eq!(ui.press(short(Button::Snooze), start), vec![Command::Run(Action::Snooze)]);
+        assert_eq!(ui.press(short(Button::B1), start),
+                   vec![Command::ClearOverlay, show("norm", Some(FEEDBACK_DURATION))]);
+        assert_eq!(ui.mode(), Mode::Normal);
+        assert_eq!(ui.press(short(Button::Right), start), vec![Command::Run(Action::NextStation)]);
+    }
+
+    #[test]
+    fn timeout() {
+        let start = Instant::now();
+        let mut ui = Ui::new(Bindings::new(&[]), TIMEOUT, Settings::default(), start);
+        ui.press(short(Button::B1), start);
+        assert!(ui.tick(start + TIMEOUT / 2).is_empty());
+        // a press restarts the delay
+        ui.press(short(Button::Left), start + TIMEOUT / 2);
+        assert!(ui.tick(start + TIMEOUT).is_empty());
+        assert_eq!(ui.tick(start + TIMEOUT * 3 / 2),
+                   vec![Command::ClearOverlay, show("norm", Some(FEEDBACK_DURATION))]);
+        assert_eq!(ui.mode(), Mode::Normal);
+        assert!(ui.tick(start + TIMEOUT * 3).is_empty());
+    }
+
+    #[test]
+    fn nap_mode() {
+        let start = Instant::now();
+        let mut ui = Ui::new(Bindings::new(&[]), TIMEOUT, Settings::default(), start);
+        let long = Press { button: Button::B1, gesture: Gesture::Long };
+        let nap = |text: &str| vec![Command::Show(text.into(), true, None)];
+        assert_eq!(ui.press(long, start), nap("n 20"));
+        assert_eq!(ui.press(short(Button::Right), start), nap("n 25"));
+        for _ in 0..4 {
+            ui.press(short(Button::Left), start);
+        }
+        assert_eq!(ui.press(short(Button::Left), start), nap("nOFF"));
+        assert_eq!(ui.press(short(Button::Left), start), nap("nOFF"));
+        ui.press(short(Button::Right), start);
+        assert_eq!(ui.press(short(Button::Time), start).last(), Some(&Command::Nap(5)));
+        assert_eq!(ui.mode(), Mode::Normal);
+        // snooze leaves without a nap, and the length starts over
+        assert_eq!(ui.press(long, start), nap("n 20"));
+        assert!(!ui.press(short(Button::Snooze), start).iter().any(|c| matches!(c, Command::Nap(_))));
+        assert_eq!(ui.mode(), Mode::Normal);
+    }
+
+    #[test]
+    fn settings_mode() {
+        let start = Instant::now();
+        let mut ui = Ui::new(Bindings::new(&[]), TIMEOUT, Settings::default(), start);
+        assert_eq!(ui.press(short(Button::B2), start), vec![show("Led", None)]);
+        assert_eq!(ui.press(short(Button::Right), start), vec![show("CEIL", None)]);
+        assert_eq!(ui.press(short(Button::Time), start), vec![Command::Show("  50".into(), true, None)]);
+        assert_eq!(ui.press(short(Button::Left), start), vec![Command::Show("  40".into(), true, None)]);
+        // snooze cancels the edition, then leaves
+        assert_eq!(ui.press(short(Button::Snooze), start), vec![show("CEIL", None)]);
+        assert_eq!(ui.press(short(Button::Snooze), start),
+                   vec![Command::ClearOverlay, show("norm", Some(FEEDBACK_DURATION))]);
+        // saved values are applied
+        ui.press(short(Button::B2), start);
+        ui.press(short(Button::Time), start);
+        ui.press(short(Button::Right), start);
+        let commands = ui.press(short(Button::Time), start);
+        let settings = Settings { regular_dim: 60, ..Settings::default() };
+        assert_eq!(commands, vec![Command::Apply(settings), show("Led", None)]);
+        assert_eq!(ui.reset(), vec![Command::ClearOverlay, show("norm", Some(FEEDBACK_DURATION))]);
+    }
+}
@@ -0,0 +1,287 @@
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::process::*;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::*;
+
+use crate::player::*;
+
+/* Client of the vlc remote control interface (vlc -Irc)
+ *
+ * Commands are written on vlc stdin, answers are read on its stdout by a thread.
+ * Answers have no end, so a query is followed by an unknown command:
+ * vlc complains about it once it has answered the query.
+ *
+ *   > status
+ *   ( new input: http://... )
+ *   ( audio volume: 256 )
+ *   ( state playing )
+ *   > clock-sync
+ *   Unknown command `clock-sync'. Type `help' for help.
+ */
+
+const MARKER: &str = "clock-sync";
+// vlc doesn't answer, it is stuck, also the bound of a whole state poll
+const TIMEOUT: Duration = Duration::from_millis(1000);
+// a pi needs some seconds to start vlc
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct RcClient {
+    process: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl RcClient {
+    // command is vlc and its arguments
+    pub fn spawn(command: &[String]) -> Result<Self> {
+        let mut process = Command::new(&command[0])
+                .args(&command[1..])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+        let stdin = process.stdin.take().expect("Failed to open stdin");
+        let stdout = process.stdout.take().expect("Failed to open stdout");
+        let (tx, lines) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if tx.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok(RcClient { process, stdin, lines })
+    }
+
+    pub fn is_running(&mut self) -> Result<bool> {
+        Ok(self.process.try_wait()?.is_none())
+    }
+
+    pub fn send(&mut self, command: &str) -> Result<()> {
+        println!("Sending {}", command);
+        writeln!(self.stdin, "{}", command)
+    }
+
+    // lines answered to a command before the deadline
+    fn query_until(&mut self, command: &str, deadline: Instant) -> Result<Vec<String>> {
+        // answers to previous commands
+        while self.lines.try_recv().is_ok() {}
+        writeln!(self.stdin, "{}\n{}", command, MARKER)?;
+        let mut answer = Vec::new();
+        loop {
+            match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) if line.contains(MARKER) => return Ok(answer),
+                Ok(line) => answer.push(strip_prompt(&line).to_string()),
+                Err(RecvTimeoutError::Timeout) =>
+                    return Err(Error::new(ErrorKind::TimedOut, format!("vlc doesn't answer {}", command))),
+                Err(RecvTimeoutError::Disconnected) =>
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "vlc is gone")),
+            }
+        }
+    }
+
+    // all the queries share one deadline, the main thread waits for them
+    pub fn state(&mut self) -> Result<PlayerState> {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut state = parse_status(&self.query_until("status", deadline)?);
+        // status only tells the volume when there is an audio output
+        if state.volume.is_none() {
+            state.volume = parse_number(&self.query_until("get_volume", deadline)?).map(|v| v as u16);
+        }
+        if state.playback == Playback::Stopped && parse_number(&self.query_until("is_playing", deadline)?) == Some(1.) {
+            state.playback = Playback::Playing;
+        }
+        // the icy title of streams, get_title only gives their name
+        state.title = parse_now_playing(&self.query_until("info", deadline)?);
+        if state.title.is_none() {
+            let title = self.query_until("get_title", deadline)?;
+            state.title = title.iter().map(|t| t.trim()).find(|t| !t.is_empty()).map(|t| t.to_string());
+        }
+        state.position = parse_number(&self.query_until("get_time", deadline)?).map(|t| t as u64);
+        Ok(state)
+    }
+}
+
+// vlc backend of the player
+pub struct Vlc {
+    rc: Option<RcClient>,
+    command: Vec<String>,
+}
+
+impl Vlc {
+    pub fn new() -> Self {
+        Vlc { rc: None, command: vec!["vlc".to_string(), "-Irc".to_string()] }
+    }
+
+    fn rc(&mut self) -> Result<&mut RcClient> {
+        self.rc.as_mut().ok_or_else(|| Error::new(ErrorKind::NotConnected, "vlc is not running"))
+    }
+}
+
+impl Backend for Vlc {
+    fn start(&mut self) -> Result<bool> {
+        if let Some(ref mut rc) = self.rc {
+            if rc.is_running()? { return Ok(false) }
+        }
+        let mut rc = RcClient::spawn(&self.command)?;
+        // answered when vlc is ready, no need to guess how long it takes
+        rc.query_until("loop on", Instant::now() + START_TIMEOUT)?;
+        self.rc = Some(rc);
+        Ok(true)
+    }
+
+    fn load(&mut self, urls: &[&str], play: bool) -> Result<()> {
+        let rc = self.rc()?;
+        rc.send("clear")?;
+        for (i, url) in urls.iter().enumerate() {
+            let action = if play && i == 0 { "add" } else { "enqueue" };
+            rc.send(&format!("{} {}", action, url))?;
+        }
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.rc()?.send("play")
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.rc()?.send("stop")
+    }
+
+    fn set_volume(&mut self, volume: u16) -> Result<()> {
+        self.rc()?.send(&format!("volume {}", volume))
+    }
+
+    fn state(&mut self) -> Result<PlayerState> {
+        let rc = match self.rc {
+            Some(ref mut rc) => rc,
+            None => return Ok(PlayerState::default()),
+        };
+        let result = rc.state();
+        // a stuck vlc is killed, the next command starts a new one
+        if let Err(ref e) = result {
+            if e.kind() == ErrorKind::TimedOut {
+                self.rc = None;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for RcClient {
+    // a stuck vlc must not stay around
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn strip_prompt(line: &str) -> &str {
+    let mut line = line;
+    while let Some(rest) = line.strip_prefix('>') {
+        line = rest.trim_start();
+    }
+    line
+}
+
+// ( name: value ) or ( name value )
+fn parse_status(lines: &[String]) -> PlayerState {
+    let mut state = PlayerState::default();
+    for line in lines {
+        let line = match line.trim().strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+            Some(l) => l.trim(),
+            None => continue,
+        };
+        if let Some(input) = line.strip_prefix("new input:") {
+            state.input = Some(input.trim().to_string());
+        } else if let Some(volume) = line.strip_prefix("audio volume:") {
+            state.volume = volume.trim().parse::<f64>().ok().map(|v| v as u16);
+        } else if let Some(playback) = line.strip_prefix("state") {
+            state.playback = match playback.trim() {
+                "playing" => Playback::Playing,
+                "opening" | "buffering" => Playback::Buffering,
+                "paused" => Playback::Paused,
+                "error" => Playback::Error,
+                _ => Playback::Stopped,
+            };
+        }
+    }
+    state
+}
+
+// | now_playing: title, in the meta data of info
+fn parse_now_playing(lines: &[String]) -> Option<String> {
+    lines.iter()
+        .find_map(|l| l.trim().strip_prefix('|').and_then(|l| l.trim().strip_prefix("now_playing:")))
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+fn parse_number(lines: &[String]) -> Option<f64> {
+    lines.iter().rev().find_map(|l| l.trim().parse::<f64>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|l| strip_prompt(l).to_string()).collect()
+    }
+
+    #[test]
+    fn status() {
+        let answer = lines("> ( new input: http://direct.example/fip.mp3 )\n( audio volume: 256.0 )\n( state playing )");
+        assert_eq!(parse_status(&answer), PlayerState {
+            playback: Playback::Playing,
+            volume: Some(256),
+            input: Some("http://direct.example/fip.mp3".to_string()),
+            ..PlayerState::default()
+        });
+        assert_eq!(parse_status(&lines("( state buffering )")).playback, Playback::Buffering);
+        assert_eq!(parse_status(&lines("( state stopped )\nstatus: returned 0 (no error)")), PlayerState::default());
+    }
+
+    #[test]
+    fn info() {
+        let answer = lines("+----[ Meta data ]\n|\n| title: FIP\n| now_playing: Le journal de 7h\n+----[ end of stream info ]");
+        assert_eq!(parse_now_playing(&answer).as_deref(), Some("Le journal de 7h"));
+        assert_eq!(parse_now_playing(&lines("| now_playing: ")), None);
+        assert_eq!(parse_number(&lines("> > 12\n")), Some(12.));
+        assert_eq!(parse_number(&lines("")), None);
+        assert_eq!(strip_prompt("> > 1"), "1");
+    }
+
+    // a vlc that only answers the marker, with the given script
+    fn stand_in(script: &str) -> Vlc {
+        Vlc { rc: None, command: vec!["sh".to_string(), "-c".to_string(), script.to_string()] }
+    }
+
+    #[test]
+    fn stuck_vlc() {
+        // answers the first query like a starting vlc, then is stuck
+        let mut vlc = stand_in("read command; read marker; echo \"Unknown command \\`$marker'.\"; cat > /dev/null");
+        assert!(vlc.start().unwrap());
+        assert!(!vlc.start().unwrap());
+        let pid = vlc.rc.as_ref().unwrap().process.id();
+        let polled = Instant::now();
+        assert_eq!(vlc.state().unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(polled.elapsed() < TIMEOUT * 2);
+        // killed, and a new one is started for the next command
+        assert!(vlc.rc.is_none());
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+        assert!(vlc.start().unwrap());
+        assert_ne!(vlc.rc.as_ref().unwrap().process.id(), pid);
+    }
+
+    #[test]
+    fn slow_vlc() {
+        // each answer is in time, all of them are not
+        let mut vlc = stand_in("while read command; do read marker; sleep 0.4; echo \"Unknown command \\`$marker'.\"; done");
+        vlc.start().unwrap();
+        let polled = Instant::now();
+        assert_eq!(vlc.state().unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(polled.elapsed() < TIMEOUT + Duration::from_millis(300));
+    }
+}
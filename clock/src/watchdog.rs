@@ -0,0 +1,278 @@
+use chrono::*;
+use std::fs;
+
+use crate::config::RadioConfig;
+use crate::player::{Playback, Player, PlayerState};
+
+/* Watchdog of the radio stream
+ *
+ * The alarm must wake us even when the radio doesn't play. Each time the
+ * player state is read, a stream that should play but is in error, stopped,
+ * buffering or doesn't move for too long has failed: the next stations are
+ * tried, then the local fallback file. A failed recording is replaced by its
+ * station live first. The failed station is tried again later.
+ * Without network there is no station to try, the fallback file plays at once.
+ *
+ * A stream playing silence has failed too, once it lasts SILENCE seconds and then
+ * the stall time. Only the native player measures the level of the sound: vlc,
+ * mpv and mpd don't tell it, with them a stream playing silence is not detected.
+ */
+
+const ROUTES: &str = "/proc/net/route";
+// radios don't stay quiet that long, talk has shorter pauses
+const SILENCE: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogEvent {
+    // play this station instead
+    Failover(usize),
+    // play the local file
+    Fallback,
+    // play the failed station again
+    Retry(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Primary,
+    // number of stations tried after the primary one
+    Failover(usize),
+    Fallback,
+}
+
+pub struct Watchdog {
+    stall: Duration,
+    failovers: usize,
+    retry: Duration,
+    stage: Stage,
+    // station that failed first
+    primary: usize,
+    // since when the stream doesn't play
+    failing: Option<DateTime<Local>>,
+    position: Option<u64>,
+    retry_at: Option<DateTime<Local>>,
+    // until the primary station plays again
+    failed: bool,
+}
+
+impl Watchdog {
+    pub fn new(config: &RadioConfig) -> Self {
+        Watchdog {
+            stall: Duration::seconds(config.stall_seconds),
+            failovers: config.failovers,
+            retry: Duration::minutes(config.retry_minutes),
+            stage: Stage::Primary,
+            primary: 0,
+            failing: None,
+            position: None,
+            retry_at: None,
+            failed: false,
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    pub fn check(&mut self, now: DateTime<Local>, player: &Player, state: &PlayerState, network: bool) -> Option<WatchdogEvent> {
+        // stopped or changed by someone else, it is not our business anymore
+        let ours = match self.stage {
+            Stage::Primary => !player.is_fallback(),
+            Stage::Failover(n) => !player.is_fallback() && player.current() == (self.primary + n) % player.station_count(),
+            Stage::Fallback => player.is_fallback(),
+        };
+        if !player.is_playing() || !ours {
+            self.reset();
+            return None;
+        }
+        if self.retry_at.is_some_and(|t| now >= t) {
+            self.stage = Stage::Primary;
+            self.retry_at = None;
+            self.failing = None;
+            self.position = None;
+            return Some(WatchdogEvent::Retry(self.primary));
+        }
+        if self.stage == Stage::Fallback { return None }
+
+        let stalled = match state.playback {
+            Playback::Playing => (state.position.is_some() && state.position == self.position)
+                || state.silence.is_some_and(|s| s >= SILENCE),
+            Playback::Paused => false,
+            Playback::Stopped | Playback::Buffering | Playback::Error => true,
+        };
+        self.position = state.position;
+        if !stalled {
+            self.failing = None;
+            if self.stage == Stage::Primary {
+                self.failed = false;
+            }
+            return None;
+        }
+        let since = *self.failing.get_or_insert(now);
+        if now - since < self.stall { return None }
+
+        self.failing = None;
+        self.position = None;
+        self.failed = true;
+        // the live station is as good as its recording
+        if self.stage == Stage::Primary && player.is_recorded() && network {
+            self.primary = player.current();
+            self.retry_at = Some(now + self.retry);
+            self.stage = Stage::Failover(0);
+            return Some(WatchdogEvent::Failover(self.primary));
+        }
+        let tried = match self.stage {
+            Stage::Failover(n) => n,
+            _ => {
+                self.primary = player.current();
+                self.retry_at = Some(now + self.retry);
+                0
+            },
+        };
+        if network && tried < self.failovers && tried + 1 < player.station_count() {
+            self.stage = Stage::Failover(tried + 1);
+            Some(WatchdogEvent::Failover((self.primary + tried + 1) % player.station_count()))
+        } else {
+            self.stage = Stage::Fallback;
+            Some(WatchdogEvent::Fallback)
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stage = Stage::Primary;
+        self.failing = None;
+        self.position = None;
+        self.retry_at = None;
+        self.failed = false;
+    }
+}
+
+// a default route, true when we cannot tell
+pub fn network_up() -> bool {
+    match fs::read_to_string(ROUTES) {
+        Ok(routes) => routes.lines().skip(1)
+            .any(|l| l.split_whitespace().nth(1) == Some("00000000")),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::Library;
+    use crate::mock::Mock;
+    use crate::stations::local_stations;
+
+    fn setup() -> (Player, Watchdog) {
+        let radio: RadioConfig = toml::from_str(r#"
+            [[stations]]
+            url = "a"
+            [[stations]]
+            url = "b"
+            [[stations]]
+            url = "c"
+        "#).unwrap();
+        let library = Library::load(std::env::temp_dir().join("clock-watchdog-test").to_str().unwrap());
+        let player = Player::new(Box::new(Mock::new(&[])), local_stations(&radio), "fallback.mp3", library);
+        (player, Watchdog::new(&radio))
+    }
+
+    fn state(playback: Playback, position: Option<u64>) -> PlayerState {
+        PlayerState { playback, position, ..PlayerState::default() }
+    }
+
+    #[test]
+    fn failover_then_retry() {
+        let (mut player, mut watchdog) = setup();
+        let start = Local::now();
+        let at = |s| start + Duration::seconds(s);
+        // not playing, not our business
+        assert_eq!(watchdog.check(at(0), &player, &state(Playback::Error, None), true), None);
+        player.play_station(1).unwrap();
+        assert_eq!(watchdog.check(at(0), &player, &state(Playback::Playing, Some(5)), true), None);
+        assert_eq!(watchdog.check(at(10), &player, &state(Playback::Playing, Some(15)), true), None);
+        // stalled for the stall time
+        assert_eq!(watchdog.check(at(20), &player, &state(Playback::Playing, Some(15)), true), None);
+        assert_eq!(watchdog.check(at(40), &player, &state(Playback::Playing, Some(15)), true), None);
+        assert!(!watchdog.failed());
+        assert_eq!(watchdog.check(at(50), &player, &state(Playback::Playing, Some(15)), true), Some(WatchdogEvent::Failover(2)));
+        assert!(watchdog.failed());
+        player.play_station(2).unwrap();
+        assert_eq!(watchdog.check(at(60), &player, &state(Playback::Buffering, None), true), None);
+        assert_eq!(watchdog.check(at(90), &player, &state(Playback::Error, None), true), Some(WatchdogEvent::Failover(0)));
+        player.play_station(0).unwrap();
+        assert_eq!(watchdog.check(at(100), &player, &state(Playback::Stopped, None), true), None);
+        assert_eq!(watchdog.check(at(130), &player, &state(Playback::Stopped, None), true), Some(WatchdogEvent::Fallback));
+        player.play_fallback().unwrap();
+        assert_eq!(watchdog.check(at(200), &player, &state(Playback::Playing, None), true), None);
+        assert!(watchdog.failed());
+        // the first failed station after the retry time
+        assert_eq!(watchdog.check(at(650), &player, &state(Playback::Playing, None), true), Some(WatchdogEvent::Retry(1)));
+        player.play_station(1).unwrap();
+        assert!(watchdog.failed());
+        assert_eq!(watchdog.check(at(660), &player, &state(Playback::Playing, Some(1)), true), None);
+        assert!(!watchdog.failed());
+    }
+
+    #[test]
+    fn offline_and_user() {
+        let (mut player, mut watchdog) = setup();
+        let start = Local::now();
+        let at = |s| start + Duration::seconds(s);
+        player.play_station(0).unwrap();
+        // no network, no station to try
+        assert_eq!(watchdog.check(at(0), &player, &state(Playback::Error, None), false), None);
+        assert_eq!(watchdog.check(at(30), &player, &state(Playback::Error, None), false), Some(WatchdogEvent::Fallback));
+        player.play_fallback().unwrap();
+        // stopped by the user
+        player.stop().unwrap();
+        assert_eq!(watchdog.check(at(40), &player, &state(Playback::Stopped, None), true), None);
+        assert!(!watchdog.failed());
+        // station changed by the user during the failover
+        player.play_station(0).unwrap();
+        watchdog.check(at(50), &player, &state(Playback::Error, None), true);
+        assert_eq!(watchdog.check(at(80), &player, &state(Playback::Error, None), true), Some(WatchdogEvent::Failover(1)));
+        player.play_station(1).unwrap();
+        player.change_url(true).unwrap();
+        assert_eq!(watchdog.check(at(90), &player, &state(Playback::Playing, None), true), None);
+        assert!(!watchdog.failed());
+    }
+
+    #[test]
+    fn recording_fails() {
+        let (mut player, mut watchdog) = setup();
+        let start = Local::now();
+        let at = |s| start + Duration::seconds(s);
+        player.play_recording("http://127.0.0.1:8731/1773122400", Some(1)).unwrap();
+        assert_eq!(watchdog.check(at(0), &player, &state(Playback::Error, None), true), None);
+        assert_eq!(watchdog.check(at(30), &player, &state(Playback::Error, None), true), Some(WatchdogEvent::Failover(1)));
+        player.play_station(1).unwrap();
+        assert!(!player.is_recorded());
+        // then the next stations as usual
+        assert_eq!(watchdog.check(at(40), &player, &state(Playback::Error, None), true), None);
+        assert_eq!(watchdog.check(at(70), &player, &state(Playback::Error, None), true), Some(WatchdogEvent::Failover(2)));
+        player.play_station(2).unwrap();
+        assert_eq!(watchdog.check(at(80), &player, &state(Playback::Playing, Some(1)), true), None);
+
+        // without network the live station fails too
+        let (mut player, mut watchdog) = setup();
+        player.play_recording("http://127.0.0.1:8731/1773122400", Some(1)).unwrap();
+        watchdog.check(at(0), &player, &state(Playback::Error, None), false);
+        assert_eq!(watchdog.check(at(30), &player, &state(Playback::Error, None), false), Some(WatchdogEvent::Fallback));
+    }
+
+    #[test]
+    fn silence() {
+        let (mut player, mut watchdog) = setup();
+        let start = Local::now();
+        let at = |s| start + Duration::seconds(s);
+        let silent = |position, silence| PlayerState { silence: Some(silence), ..state(Playback::Playing, Some(position)) };
+        player.play_station(0).unwrap();
+        // pauses are not silence
+        assert_eq!(watchdog.check(at(0), &player, &silent(0, 0), true), None);
+        assert_eq!(watchdog.check(at(10), &player, &silent(10, 2), true), None);
+        assert_eq!(watchdog.check(at(20), &player, &silent(20, 12), true), None);
+        assert_eq!(watchdog.check(at(40), &player, &silent(40, 32), true), None);
+        assert_eq!(watchdog.check(at(50), &player, &silent(50, 42), true), Some(WatchdogEvent::Failover(1)));
+    }
+}